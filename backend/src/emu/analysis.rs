@@ -0,0 +1 @@
+../../../interpreter/src/analysis.rs
\ No newline at end of file
@@ -0,0 +1 @@
+../../../interpreter/src/inspect.rs
\ No newline at end of file
@@ -1,9 +1,14 @@
+pub mod analysis;
 pub mod assembler;
+pub mod inspect;
 pub mod instruction;
 pub mod interpreter;
 pub mod machine;
 pub mod modules;
+pub mod paradox;
+pub mod power;
 pub mod prelude;
 pub mod state;
+pub mod timing;
 pub mod universe;
 pub mod word;
\ No newline at end of file
@@ -0,0 +1 @@
+../../../interpreter/src/paradox.rs
\ No newline at end of file
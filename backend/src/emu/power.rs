@@ -0,0 +1 @@
+../../../interpreter/src/power.rs
\ No newline at end of file
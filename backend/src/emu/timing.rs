@@ -0,0 +1 @@
+../../../interpreter/src/timing.rs
\ No newline at end of file
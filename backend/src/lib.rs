@@ -8,9 +8,11 @@ use std::sync::{mpsc, Arc, Once};
 use std::thread;
 
 use crate::emu::assembler;
-use crate::emu::modules::{ClockModule, DisplayModule, ModuleCollection};
-use crate::emu::universe::Universe;
-use crate::emu::interpreter;
+use crate::emu::modules::{ClockModule, DisplayModule, IoLog, ModuleCollection};
+use crate::emu::universe::{Universe, UniverseConfig};
+use crate::emu::interpreter::{self, StepOutcome};
+use crate::emu::power;
+use crate::emu::timing::{self, Throttle};
 
 mod emu;
 
@@ -28,6 +30,11 @@ struct Report {
     stack: u32,
     /*had_time_jump: bool,*/
     history: Vec<String>,
+    /// Draw of the Novikov module, in kW
+    power: u32,
+    /// Energy drawn along the timeline, in J
+    energy: u32,
+    brown_out: bool,
 }
 
 impl Default for Report {
@@ -38,6 +45,9 @@ impl Default for Report {
             stack: Default::default(),
             /*had_time_jump: Default::default(),*/
             history: Default::default(),
+            power: Default::default(),
+            energy: Default::default(),
+            brown_out: Default::default(),
         }
     }
 }
@@ -76,6 +86,9 @@ impl Report {
             }
             array
         };
+        let power = cx.number(self.power);
+        let energy = cx.number(self.energy);
+        let brown_out = cx.boolean(self.brown_out);
 
         let object = JsObject::new(cx);
 
@@ -84,6 +97,9 @@ impl Report {
         object.set(cx, "stack", stack)?;
         /*object.set(cx, "had_time_jump", had_time_jump)?;*/
         object.set(cx, "history", history)?;
+        object.set(cx, "power", power)?;
+        object.set(cx, "energy", energy)?;
+        object.set(cx, "brown_out", brown_out)?;
 
         Ok(object)
     }
@@ -130,8 +146,30 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
 
             // Emulation
 
-            let mut universe = Universe::new();
-            assembler::assemble_into(universe.now_mut(), include_str!("program.asm"));
+            let config = match std::env::var("UNIVERSE") {
+                Ok(config) => UniverseConfig::parse(&config).expect("Invalid UNIVERSE config."),
+                Err(_) => UniverseConfig::default(),
+            };
+            let mut universe = Universe::new(config);
+            if let Ok(strategy) = std::env::var("STRATEGY") {
+                universe.strategy = interpreter::parse_strategy(&strategy).expect("Invalid strategy.");
+            }
+            if let Ok(unit) = std::env::var("TIMING") {
+                universe.unit = timing::parse_unit(&unit).expect("Invalid TIMING unit.");
+            }
+            if let Ok(supply) = std::env::var("POWER") {
+                universe.power = power::parse_power(&supply).expect("Invalid POWER supply.");
+            }
+            let max_jump = universe.config.max_jump;
+            if let Err(errors) = assembler::assemble_into(universe.now_mut(), include_str!("program.asm"), max_jump) {
+                for error in errors { eprintln!("{}", error) };
+                panic!("Failed to assemble program.");
+            }
+            // Keep to the nominal clock speed, however often we are polled
+            let throttle = std::env::var("THROTTLE").ok().map(|_| Throttle::new(universe.cycles()));
+
+            // Kept across steps, so that IO is replayed after a rewind
+            let mut io_log = IoLog::default();
 
             let mut cmd_history = VecDeque::new();
             cmd_history.resize_with(6, || "nop".to_string());
@@ -142,23 +180,34 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
                     Box::new(&mut clock_module),
                     Box::new(&mut display_module),
                 ]);
-                io_modules.run(&mut universe);
-                match interpreter::step(&mut universe) {
-                    None => {
-                        eprintln!("Consistency failure. Resetting machine.");
+                io_modules.log = std::mem::take(&mut io_log);
+                let result = interpreter::step(&mut universe, &mut io_modules);
+                io_log = std::mem::take(&mut io_modules.log);
+                match result {
+                    Err(err) => {
+                        eprintln!("{}. Resetting machine.", err);
                         continue 'emu; // Reset the machine on panic
                     }
-                    Some((machine, instruction)) => {
+                    Ok((machine, outcome)) => {
+                        if let Some(throttle) = &throttle { throttle.wait(machine.cycles) };
+
                         // Read the information
-                        cmd_history.pop_back();
-                        cmd_history.push_front(emu::assembler::mnemonic(instruction));
+                        let command = match outcome {
+                            StepOutcome::Running(instruction) => Some(instruction.to_string()),
+                            StepOutcome::Halted => Some("hcf".to_string()),
+                            StepOutcome::Busy => None,
+                        };
+                        if let Some(command) = command {
+                            cmd_history.pop_back();
+                            cmd_history.push_front(command);
+                        }
 
                         // Read the information
 
                         let hours = (&display_module.hours).clone();
                         let minutes = (&display_module.minutes).clone();
 
-                        let stack = 0x7f - machine.cpu.sp.value() as u32;
+                        let stack = machine.stack_depth().unwrap_or(0) as u32;
 
                         let registers = {
                             let mut registers = [[false; 6]; 9];
@@ -169,7 +218,7 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
                                 }
                             };
 
-                            value_as_bits(machine.cpu.flags.word.value() as u16, &mut registers[0]);
+                            value_as_bits(machine.cpu.flags.word().value() as u16, &mut registers[0]);
                             value_as_bits(machine.cpu.a.value() as u16, &mut registers[1]);
                             value_as_bits(machine.cpu.bh.value() as u16, &mut registers[2]);
                             value_as_bits(machine.cpu.bl.value() as u16, &mut registers[3]);
@@ -182,6 +231,8 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
                             registers
                         };
 
+                        let (power, brown_out, energy) = power::readings(&machine);
+
                         /*let had_time_jump = ();*/
 
                         let dummy_report = Report {
@@ -190,6 +241,9 @@ fn main(mut cx: ModuleContext) -> NeonResult<()> {
                             stack,
                             /*had_time_jump,*/
                             history: cmd_history.iter().cloned().collect(),
+                            power: power as u32,
+                            energy: energy as u32,
+                            brown_out,
                         };
 
                         if response_channel.send(dummy_report).is_err() {
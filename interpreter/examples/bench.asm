@@ -0,0 +1,12 @@
+; Benchmark: touches the top of memory, so that every state holds all of RAM,
+; then counts to 64×64 (some 8k instructions). Run with
+;   BENCH=1 cargo run --release < examples/bench.asm > /dev/null
+
+mov #01 %3f3f
+
+:loop
+inc %0010
+bne loop
+inc %0110
+bne loop
+hcf
@@ -0,0 +1,19 @@
+; Benchmark for temporal operands: every iteration reads the counter from the
+; future, writes it into the future, and reads from the past. Run with
+;   BENCH=1 cargo run --release < examples/bench_time.asm > /dev/null
+
+mov #20 %0011
+
+:loop
+mov %0010@+2 a
+mov a %0110@+1500
+add %0110@-3 a
+inc %0010
+beq carry
+jmp loop
+:carry
+inc %0011
+beq end
+jmp loop
+:end
+hcf
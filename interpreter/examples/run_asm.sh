@@ -1 +1 @@
-cat $@ | python3 src/preprocess_asm.py | cargo run
+cat $@ | cargo run
@@ -0,0 +1,300 @@
+//! Enumeration of every self-consistent timeline of a program
+
+use crate::prelude::*;
+use crate::interpreter::{step_micro, ConsistencyStrategy, EmuError};
+use crate::power::PowerModel;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Values assumed for reads from the future, in the order they are first met
+#[derive(Default)]
+struct Choices {
+    /// Each read (time, operand) met so far
+    reads: Vec<(usize, Op)>,
+    /// Values for the first reads; the rest are still to be chosen
+    values: Vec<uWord>,
+    /// Whether a read past the chosen ones was met
+    unchosen: bool,
+}
+
+/// Guesses the chosen values, and never changes its mind
+struct Forced(Rc<RefCell<Choices>>);
+
+impl ConsistencyStrategy for Forced {
+    fn guess(&mut self, t: usize, op: &Op, _previous: Option<uWord>) -> uWord {
+        let mut choices = self.0.borrow_mut();
+        let i = match choices.reads.iter().position(|x| x.0 == t && &x.1 == op) {
+            Some(i) => i,
+            None => { choices.reads.push((t, op.clone())); choices.reads.len() - 1 }
+        };
+        match choices.values.get(i) {
+            Some(x) => *x,
+            None => { choices.unchosen = true; uWord::ZERO }
+        }
+    }
+}
+
+/// Limits on the search
+#[derive(Debug, Clone)]
+pub struct Bounds {
+    /// Stop after finding this many consistent timelines
+    pub timelines: usize,
+    /// Give up on a run after this many micro steps (it might not halt)
+    pub steps: usize,
+    /// Give up on the search after this many runs (each read from the future
+    /// multiplies them by 64)
+    pub runs: usize,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds { timelines: 16, steps: 1000, runs: 10_000 }
+    }
+}
+
+/// A consistent timeline
+#[derive(Debug, Clone)]
+pub struct History {
+    /// The value of each read from the future, in the order they were met
+    pub reads: Vec<(usize, Op, uWord)>,
+    /// The final state, halted or faulted
+    pub machine: Machine,
+}
+
+/// All consistent timelines found
+#[derive(Debug, Clone)]
+pub struct Enumeration {
+    pub histories: Vec<History>,
+    /// Runs tried, consistent or not
+    pub runs: usize,
+    /// Runs that did not settle within the step bound
+    pub unfinished: usize,
+    /// Whether every choice was explored (else, stopped at a bound)
+    pub complete: bool,
+    /// Whether the search stopped at the bound on runs
+    pub out_of_runs: bool,
+}
+
+/// How a run with some choices ended
+enum Run {
+    Consistent(Machine),
+    Inconsistent,
+    /// Met a read with no value chosen yet
+    Unchosen,
+    Unfinished,
+}
+
+/// Runs the present state of `template` with the same config, time unit and
+/// power supply
+fn run(template: &Universe, values: &[uWord], bounds: &Bounds) -> Result<(Run, Vec<(usize, Op)>), EmuError> {
+    let choices = Rc::new(RefCell::new(Choices { values: values.to_vec(), ..Default::default() }));
+    let mut universe = Universe::new(template.config.clone());
+    *universe.now_mut() = template.now().clone();
+    universe.unit = template.unit;
+    let power = &template.power;
+    universe.power = PowerModel { supply: power.supply, on_brown_out: power.on_brown_out, ..Default::default() };
+    universe.strategy = Box::new(Forced(choices.clone()));
+    // IO modules are left out: they are not deterministic
+    let mut modules = ModuleCollection::new(vec![]);
+    let mut result = Run::Unfinished;
+    for _ in 0..bounds.steps {
+        match step_micro(&mut universe, &mut modules) {
+            Ok(_) | Err(EmuError::Fault(_)) => (),
+            Err(err) => return Err(err),
+        }
+        if choices.borrow().unchosen { result = Run::Unchosen; break }
+        // Any rewind means the values chosen are not a fixed point
+        if universe.paradox.last().is_some() { result = Run::Inconsistent; break }
+        if universe.is_settled() { result = Run::Consistent(universe.now().clone()); break }
+    }
+    let reads = choices.borrow().reads.clone();
+    Ok((result, reads))
+}
+
+/// Finds the consistent timelines of the present state of `universe`, by
+/// trying every value for each read from the future (depth first, in the order
+/// the reads are met), and keeping those that never need to rewind
+pub fn enumerate(universe: &Universe, bounds: &Bounds) -> Result<Enumeration, EmuError> {
+    let mut enumeration = Enumeration { histories: vec![], runs: 0, unfinished: 0, complete: true, out_of_runs: false };
+    let mut stack: Vec<Vec<uWord>> = vec![vec![]];
+    while let Some(values) = stack.pop() {
+        if enumeration.histories.len() == bounds.timelines {
+            enumeration.complete = false;
+            break
+        }
+        if enumeration.runs == bounds.runs {
+            enumeration.complete = false;
+            enumeration.out_of_runs = true;
+            break
+        }
+        enumeration.runs += 1;
+        match run(universe, &values, bounds)? {
+            (Run::Consistent(machine), reads) => {
+                let reads = reads.into_iter().zip(values).map(|((t, op), x)| (t, op, x)).collect();
+                enumeration.histories.push(History { reads, machine })
+            }
+            (Run::Inconsistent, _) => (),
+            (Run::Unfinished, _) => {
+                enumeration.unfinished += 1;
+                enumeration.complete = false;
+            }
+            // Branch on the next read, smallest values first
+            (Run::Unchosen, _) => {
+                for x in (0..=uWord::MAX.value()).rev() {
+                    let mut values = values.clone();
+                    values.push(uWord::try_from(x).unwrap());
+                    stack.push(values);
+                }
+            }
+        }
+    }
+    Ok(enumeration)
+}
+
+/// Registers compared between timelines
+fn registers(m: &Machine) -> [(&'static str, u16); 9] {
+    [
+        ("a", m.cpu.a.value() as u16),
+        ("f", m.cpu.flags.word().value() as u16),
+        ("bh", m.cpu.bh.value() as u16),
+        ("bl", m.cpu.bl.value() as u16),
+        ("ch", m.cpu.ch.value() as u16),
+        ("cl", m.cpu.cl.value() as u16),
+        ("x", m.cpu.x.value() as u16),
+        ("sp", m.cpu.sp.value()),
+        ("pc", m.cpu.pc.value()),
+    ]
+}
+
+impl Enumeration {
+    /// Registers and memory locations whose final value is not the same in
+    /// every timeline, with the value in each
+    pub fn differences(&self) -> Vec<(String, Vec<u16>)> {
+        let machines = self.histories.iter().map(|x| &x.machine).collect::<Vec<_>>();
+        let differ = |values: &Vec<u16>| values.iter().any(|x| x != &values[0]);
+        let mut differences = vec![];
+        for i in 0..9 {
+            let values = machines.iter().map(|m| registers(m)[i].1).collect();
+            if differ(&values) { differences.push((registers(machines[0])[i].0.to_string(), values)) }
+        }
+        let len = machines.iter().map(|m| m.ram.end()).max().unwrap_or(0);
+        for i in 0..len {
+            let values = machines.iter().map(|m| m.ram[i].value() as u16).collect();
+            if differ(&values) {
+                let address = Address::try_from(i as u16).unwrap();
+                differences.push((Op::Abs(address).to_string(), values))
+            }
+        }
+        differences
+    }
+}
+
+impl std::fmt::Display for Enumeration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.histories.len();
+        write!(f, "{} consistent timeline{}", n, if n == 1 { "" } else { "s" })?;
+        match self.complete {
+            true => writeln!(f, " ({} runs).", self.runs)?,
+            false => writeln!(f, " found before stopping ({} runs, {} did not settle).", self.runs, self.unfinished)?,
+        }
+        if self.out_of_runs { writeln!(f, "Gave up at the bound on runs: the search is not exhaustive.")? };
+        for (i, history) in self.histories.iter().enumerate() {
+            write!(f, "  #{}:", i+1)?;
+            if history.reads.is_empty() { write!(f, " no reads from the future")? };
+            for (i, (t, op, x)) in history.reads.iter().enumerate() {
+                if i > 0 { write!(f, ",")? };
+                write!(f, " {} at t={} = {:02x}", op, t, x.value())?;
+            }
+            writeln!(f)?;
+        }
+        let differences = self.differences();
+        if !differences.is_empty() {
+            writeln!(f, "Final values that differ:")?;
+            for (name, values) in differences {
+                write!(f, "  {:>5}:", name)?;
+                for x in values { write!(f, " {:02x}", x)? };
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_into;
+    use crate::universe::MAX_JUMP;
+
+    fn enumerate_in(mut universe: Universe, source: &str, bounds: &Bounds) -> Enumeration {
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        enumerate(&universe, bounds).unwrap()
+    }
+
+    fn enumerate_source(source: &str, bounds: &Bounds) -> Enumeration {
+        enumerate_in(Universe::new(UniverseConfig::default()), source, bounds)
+    }
+
+    #[test]
+    fn no_reads() {
+        let enumeration = enumerate_source("mov #05 a\nhcf", &Bounds::default());
+        assert!(enumeration.complete);
+        assert_eq!(enumeration.histories.len(), 1);
+        assert!(enumeration.differences().is_empty());
+    }
+
+    #[test]
+    fn two_timelines() {
+        // Writes back 2 - x: consistent with x = 1 or 33
+        let source = "mov %0001@+4 a\nnot a\nadd #03 a\nmov a %0001\nhcf";
+        let enumeration = enumerate_source(source, &Bounds::default());
+        assert!(enumeration.complete);
+        let values = enumeration.histories.iter().map(|x| x.machine.ram[0x40].value()).collect::<Vec<_>>();
+        assert_eq!(values, vec![0x01, 0x21]);
+        let differences = enumeration.differences();
+        assert!(differences.contains(&("a".to_string(), vec![0x01, 0x21])));
+        assert!(differences.contains(&("%0001".to_string(), vec![0x01, 0x21])));
+    }
+
+    #[test]
+    fn none() {
+        // Writes back the negation of what it reads
+        let source = "mov %0001@+3 a\nnot a\nmov a %0001\nhcf";
+        let enumeration = enumerate_source(source, &Bounds::default());
+        assert!(enumeration.complete);
+        assert!(enumeration.histories.is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        // Any value is consistent
+        let source = "mov %0001@+3 a\nmov a %0001\nhcf";
+        let enumeration = enumerate_source(source, &Bounds { timelines: 4, ..Default::default() });
+        assert!(!enumeration.complete);
+        assert_eq!(enumeration.histories.len(), 4);
+    }
+
+    #[test]
+    fn out_of_runs() {
+        let source = "mov %0001@+3 a\nmov a %0001\nhcf";
+        let enumeration = enumerate_source(source, &Bounds { timelines: 100, runs: 10, ..Default::default() });
+        assert!(!enumeration.complete && enumeration.out_of_runs);
+        assert_eq!(enumeration.runs, 10);
+        assert!(enumeration.to_string().contains("Gave up at the bound on runs"));
+    }
+
+    #[test]
+    fn universe_settings() {
+        // Any value is consistent, unless the supply browns out before the read
+        let source = "mov %0001@+100 a\nhcf";
+        let enumeration = enumerate_source(source, &Bounds { timelines: 4, ..Default::default() });
+        assert_eq!(enumeration.histories.len(), 4);
+
+        let mut universe = Universe::new(UniverseConfig::default());
+        universe.power = crate::power::parse_power("sps-3-5000").unwrap();
+        let enumeration = enumerate_in(universe, source, &Bounds::default());
+        assert!(enumeration.complete);
+        assert_eq!(enumeration.histories.len(), 1);
+        assert_eq!(enumeration.histories[0].machine.cpu.fault, Some(Fault::BrownOut(100)));
+    }
+}
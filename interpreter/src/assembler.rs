@@ -1,132 +1,361 @@
 use crate::prelude::*;
 
-use super::instruction::{Instruction, Op, Operand, Operands, Register, Timed};
+use super::instruction::{Instruction, Offset, Op, Operand, Operands, Pair, Register, Timed};
 use std::iter::Peekable;
-use std::str::{CharIndices, Lines};
-use radix_fmt::radix;
+use std::str::CharIndices;
+
+use std::collections::HashMap;
+
+/// Address at which programs are assembled (start of EPROM)
+pub const ORIGIN: usize = 0x80;
+
+// Errors //
+
+/// What went wrong while assembling a statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    /// Not one of the known instructions
+    UnknownMnemonic(String),
+    /// Unexpected character where an operand was expected
+    BadOperand,
+    /// Literal that does not fit in its field (e.g. `#40`)
+    OutOfRange(String),
+    /// Statement ended where an operand was expected
+    MissingOperand,
+    /// Unexpected characters after a complete instruction
+    TrailingGarbage,
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    /// Label that reads as a literal address `llhh` (e.g. `:beef`)
+    AddressLabel(String),
+    /// Branch target is not within reach of an `Offset`, by this many words
+    BranchOutOfRange(isize),
+    /// Branch target is past the start/end of the program
+    BranchOutOfProgram,
+    /// Time jump farther than the limit (the second field)
+    JumpOutOfRange(isize, usize),
+}
 
-pub struct InstructionIterator<'i> {
-    lines: Lines<'i>,
-    line_idx: usize,
+impl std::fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use AsmErrorKind::*;
+        match self {
+            UnknownMnemonic(x) => write!(f, "unknown mnemonic `{}`", x),
+            BadOperand => write!(f, "bad operand"),
+            OutOfRange(x) => write!(f, "literal `{}` out of range", x),
+            MissingOperand => write!(f, "missing operand"),
+            TrailingGarbage => write!(f, "unexpected characters after instruction"),
+            UnknownLabel(x) => write!(f, "unknown label `{}`", x),
+            DuplicateLabel(x) => write!(f, "duplicate label `{}`", x),
+            AddressLabel(x) => write!(f, "label `{}` reads as an address", x),
+            BranchOutOfRange(x) => write!(f, "branch offset {:+} out of range", x),
+            BranchOutOfProgram => write!(f, "branch target out of program"),
+            JumpOutOfRange(x, max) => write!(f, "time jump {:+} farther than the limit of {}", x, max),
+        }
+    }
 }
 
-impl<'i> Iterator for InstructionIterator<'i> {
-    type Item = Instruction;
+/// An assembly error, located in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    /// Line number (1-based)
+    pub line: usize,
+    /// Column number, in chars (1-based)
+    pub column: usize,
+    /// The offending source line
+    pub snippet: String,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut line = "";
-        while line.is_empty() {
-            let iter_line = self.lines.next();
-            self.line_idx += 1;
-            if iter_line.is_none() {
-                return None;
-            }
-            line = iter_line.unwrap();
-            line = match line.split_once(";") {
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep tabs, so that the caret lines up with the snippet
+        let indent = self.snippet.chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        writeln!(f, "error at {}:{}: {}", self.line, self.column, self.kind)?;
+        writeln!(f, "    {}", self.snippet)?;
+        write!(f, "    {}^", indent)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// An error at a (byte) position of the statement being read
+#[derive(Debug)]
+struct ReadError(AsmErrorKind, usize);
+
+type ReadResult<T> = Result<T, ReadError>;
+
+impl ReadError {
+    /// Error for whatever comes next in `chars`: a missing or a bad operand
+    fn unexpected(chars: &mut SlidingWindow) -> Self {
+        match chars.peek() {
+            None => ReadError(AsmErrorKind::MissingOperand, chars.next_pos()),
+            Some(_) => ReadError(AsmErrorKind::BadOperand, chars.next_pos()),
+        }
+    }
+}
+
+// Assembly //
+
+/// A non-empty source line, stripped of comments
+#[derive(Debug, Clone, Copy)]
+enum Statement<'i> {
+    /// `:name`, anything after the name is ignored
+    Label(&'i str),
+    Instruction(&'i str),
+}
+
+/// Where a statement comes from
+#[derive(Debug, Clone, Copy)]
+struct Source<'i> {
+    /// Line number (1-based)
+    line: usize,
+    /// The whole line
+    text: &'i str,
+    /// Byte offset of the statement in `text`
+    start: usize,
+}
+
+impl<'i> Source<'i> {
+    fn error(&self, ReadError(kind, pos): ReadError) -> AsmError {
+        AsmError {
+            kind,
+            line: self.line,
+            column: self.text[..self.start + pos].chars().count() + 1,
+            snippet: self.text.trim_end().to_string(),
+        }
+    }
+}
+
+/// Splits the input into statements
+fn statements(input: &str) -> Vec<(Source<'_>, Statement<'_>)> {
+    input.lines()
+        .enumerate()
+        .filter_map(|(idx, text)| {
+            let line = match text.split_once(";") {
                 Some((prefix, _suffix)) => prefix,
-                None => line,
+                None => text,
             };
-            line = match line.split_once(":") {
-                Some((prefix, _suffix)) => prefix,
-                None => line,
+            let line = line.trim();
+            let source = Source {
+                line: idx + 1,
+                text,
+                start: line.as_ptr() as usize - text.as_ptr() as usize,
             };
-            line = line.trim();
+            if line.is_empty() {
+                None
+            } else if let Some(label) = line.strip_prefix(':') {
+                let name = label.split_whitespace().next().unwrap_or("");
+                Some((source, Statement::Label(name)))
+            } else {
+                Some((source, Statement::Instruction(line)))
+            }
+        })
+        .collect()
+}
+
+/// Addresses computed by the first pass
+struct Symbols<'i> {
+    labels: HashMap<&'i str, Address>,
+    /// Address of each statement, plus one-past-the-end of the program
+    statements: Vec<Address>,
+}
+
+/// Where the statement being read sits in the program. During the first pass 
+/// `symbols` is `None`, and every reference resolves to a placeholder (sizes 
+/// do not depend on the values).
+#[derive(Clone, Copy)]
+struct Context<'s, 'i> {
+    symbols: Option<&'s Symbols<'i>>,
+    statement: usize,
+    /// Farthest time jump allowed
+    max_jump: usize,
+}
+
+/// An assembled statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Instruction(Instruction),
+    /// Raw data word, `dat xx`
+    Data(uWord),
+}
+
+impl Item {
+    pub fn encode(&self, m: &mut Machine) {
+        match self {
+            Item::Instruction(x) => x.encode(m),
+            Item::Data(x) => m.write_pc(*x),
         }
-        Some(read_instruction(line, self.line_idx))
+    }
+
+    /// Number of words once encoded
+    fn size(&self) -> usize {
+        let mut m = Machine::new();
+        let start = usize::from(m.cpu.pc);
+        m.program(|m| self.encode(m));
+        usize::from(m.cpu.pc) - start
     }
 }
 
-pub fn assemble<'i>(input: &'i str) -> InstructionIterator<'i> {
-    InstructionIterator {
-        lines: input.lines(),
-        line_idx: 0,
+/// An assembled program
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// Address of the first item
+    pub origin: Address,
+    pub items: Vec<Item>,
+}
+
+impl Program {
+    /// Writes the program into `m`, starting at `origin`
+    pub fn encode(&self, m: &mut Machine) {
+        m.cpu.pc = self.origin;
+        for i in self.items.iter() {
+            i.encode(m);
+        }
     }
 }
 
-pub fn assemble_into(m: &mut Machine, input: &str) {
-    for i in assemble(input) {
-        i.encode(m);
+/// Two-pass assembly: the first pass computes the address of every label from 
+/// the instruction sizes, the second resolves references to them. Reports 
+/// every error in the input, in order.
+/// Time jumps are limited to `max_jump` (see [`UniverseConfig`]).
+pub fn assemble(input: &str, max_jump: usize) -> Result<Program, Vec<AsmError>> {
+    let statements = statements(input);
+    let origin = Address::try_from(ORIGIN as u16).unwrap();
+    let mut errors = vec![];
+    // Statements with syntax errors are skipped on the second pass, so that 
+    // each error is reported once
+    let mut valid = vec![true; statements.len()];
+
+    // First pass
+    let mut symbols = Symbols {
+        labels: HashMap::new(),
+        statements: Vec::with_capacity(statements.len() + 1),
+    };
+    let mut cursor = origin;
+    for (idx, (source, statement)) in statements.iter().enumerate() {
+        symbols.statements.push(cursor);
+        match statement {
+            Statement::Label(name) => {
+                // References could not tell it from the address
+                if parse_address(name).is_some() {
+                    let kind = AsmErrorKind::AddressLabel(name.to_string());
+                    errors.push(source.error(ReadError(kind, 0)));
+                } else if symbols.labels.insert(name, cursor).is_some() {
+                    let kind = AsmErrorKind::DuplicateLabel(name.to_string());
+                    errors.push(source.error(ReadError(kind, 0)));
+                }
+            }
+            Statement::Instruction(line) => {
+                let context = Context { symbols: None, statement: idx, max_jump };
+                match read_item(line, context) {
+                    Ok(item) => cursor = cursor + item.size() as i32,
+                    Err(err) => {
+                        errors.push(source.error(err));
+                        valid[idx] = false;
+                    }
+                }
+            }
+        }
     }
+    symbols.statements.push(cursor);
+
+    // Second pass
+    let mut items = vec![];
+    for (idx, (source, statement)) in statements.iter().enumerate() {
+        if let (Statement::Instruction(line), true) = (statement, valid[idx]) {
+            let context = Context { symbols: Some(&symbols), statement: idx, max_jump };
+            match read_item(line, context) {
+                Ok(item) => items.push(item),
+                Err(err) => errors.push(source.error(err)),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(Program { origin, items })
+    } else {
+        errors.sort_by_key(|x| (x.line, x.column));
+        Err(errors)
+    }
+}
 
-    m.cpu.pc = Address::try_from(0x80).unwrap();
+pub fn assemble_into(m: &mut Machine, input: &str, max_jump: usize) -> Result<(), Vec<AsmError>> {
+    let program = assemble(input, max_jump)?;
+    m.program(|m| program.encode(m));
+    m.cpu.pc = program.origin;
+    Ok(())
 }
 
-fn read_instruction(literal: &str, line_idx: usize) -> Instruction {
+fn read_item(literal: &str, context: Context) -> ReadResult<Item> {
     let mut source = WindowSource::new(literal);
     let mut iter = source.window();
 
-    let mnemonic = {
-        iter.take_while(|c| !c.is_whitespace())
-            .collect()
-            .expect("Could not find a mnemonic.")
-    };
+    let mnemonic = iter.read_while(|c| !c.is_whitespace());
 
     eat_whitespace(&mut iter);
 
     let instruction = match mnemonic {
-        "mov" => Instruction::Mov(read_operands(&mut iter).or_panic(line_idx)),
-        "psh" => Instruction::Psh(read_operand(&mut iter).or_panic(line_idx)),
-        "pop" => Instruction::Pop(read_operand(&mut iter).or_panic(line_idx)),
-        "add" => Instruction::Add(read_operands(&mut iter).or_panic(line_idx)),
-        "sub" => Instruction::Sub(read_operands(&mut iter).or_panic(line_idx)),
-        "mul" => Instruction::Mul(read_operands(&mut iter).or_panic(line_idx)),
-        "muh" => Instruction::Muh(read_operands(&mut iter).or_panic(line_idx)),
-        "mus" => Instruction::Mus(read_operands(&mut iter).or_panic(line_idx)),
-        "div" => Instruction::Div(read_operands(&mut iter).or_panic(line_idx)),
-        "mod" => Instruction::Mod(read_operands(&mut iter).or_panic(line_idx)),
-        "and" => Instruction::And(read_operands(&mut iter).or_panic(line_idx)),
-        "or" => Instruction::Or(read_operands(&mut iter).or_panic(line_idx)),
-        "xor" => Instruction::Xor(read_operands(&mut iter).or_panic(line_idx)),
-        "not" => Instruction::Not(read_operand(&mut iter).or_panic(line_idx)),
-        "lsl" => Instruction::Lsl(read_operand(&mut iter).or_panic(line_idx)),
-        "lsr" => Instruction::Lsr(read_operand(&mut iter).or_panic(line_idx)),
-        "asr" => Instruction::Asr(read_operand(&mut iter).or_panic(line_idx)),
-        "inc" => Instruction::Inc(read_operand(&mut iter).or_panic(line_idx)),
-        "dec" => Instruction::Dec(read_operand(&mut iter).or_panic(line_idx)),
-        "cmp" => Instruction::Cmp(read_operands(&mut iter).or_panic(line_idx)),
-        "bit" => Instruction::Bit(read_operands(&mut iter).or_panic(line_idx)),
-        "jmp" => Instruction::Jmp(read_address(&mut iter).or_panic(line_idx)),
-        "bcc" => Instruction::Bcc(read_decimal(&mut iter).or_panic(line_idx)),
-        "bcs" => Instruction::Bcs(read_decimal(&mut iter).or_panic(line_idx)),
-        "bne" => Instruction::Bne(read_decimal(&mut iter).or_panic(line_idx)),
-        "beq" => Instruction::Beq(read_decimal(&mut iter).or_panic(line_idx)),
-        "bpl" => Instruction::Bpl(read_decimal(&mut iter).or_panic(line_idx)),
-        "bmi" => Instruction::Bmi(read_decimal(&mut iter).or_panic(line_idx)),
+        "mov" => Instruction::Mov(read_operands(&mut iter, context)?),
+        "xch" => Instruction::Xch(read_operands(&mut iter, context)?),
+        "psh" => Instruction::Psh(read_operand(&mut iter, context)?),
+        "pop" => Instruction::Pop(read_operand(&mut iter, context)?),
+        "add" => Instruction::Add(read_operands(&mut iter, context)?),
+        "sub" => Instruction::Sub(read_operands(&mut iter, context)?),
+        "mul" => Instruction::Mul(read_operands(&mut iter, context)?),
+        "muh" => Instruction::Muh(read_operands(&mut iter, context)?),
+        "mus" => Instruction::Mus(read_operands(&mut iter, context)?),
+        "div" => Instruction::Div(read_operands(&mut iter, context)?),
+        "dis" => Instruction::Dis(read_operands(&mut iter, context)?),
+        "mod" => Instruction::Mod(read_operands(&mut iter, context)?),
+        "mos" => Instruction::Mos(read_operands(&mut iter, context)?),
+        "and" => Instruction::And(read_operands(&mut iter, context)?),
+        "or" => Instruction::Or(read_operands(&mut iter, context)?),
+        "xor" => Instruction::Xor(read_operands(&mut iter, context)?),
+        "not" => Instruction::Not(read_operand(&mut iter, context)?),
+        "lsl" => Instruction::Lsl(read_operand(&mut iter, context)?),
+        "lsr" => Instruction::Lsr(read_operand(&mut iter, context)?),
+        "asr" => Instruction::Asr(read_operand(&mut iter, context)?),
+        "inc" => Instruction::Inc(read_operand(&mut iter, context)?),
+        "dec" => Instruction::Dec(read_operand(&mut iter, context)?),
+        "cmp" => Instruction::Cmp(read_operands(&mut iter, context)?),
+        "bit" => Instruction::Bit(read_operands(&mut iter, context)?),
+        "jmp" => Instruction::Jmp(read_reference(&mut iter, context)?),
+        "bcc" => Instruction::Bcc(read_branch(&mut iter, context)?),
+        "bcs" => Instruction::Bcs(read_branch(&mut iter, context)?),
+        "bne" => Instruction::Bne(read_branch(&mut iter, context)?),
+        "beq" => Instruction::Beq(read_branch(&mut iter, context)?),
+        "bpl" => Instruction::Bpl(read_branch(&mut iter, context)?),
+        "bmi" => Instruction::Bmi(read_branch(&mut iter, context)?),
         "clc" => Instruction::Clc,
         "sec" => Instruction::Sec,
-        "cal" => Instruction::Cal(read_address(&mut iter).or_panic(line_idx)),
+        "cal" => Instruction::Cal(read_reference(&mut iter, context)?),
         "ret" => Instruction::Ret,
         "nop" => Instruction::Nop,
         "hcf" => Instruction::Hcf,
-        _ => panic!("Invalid mnemonic {}", mnemonic),
+        "dat" => return read_data(&mut iter),
+        _ => return Err(ReadError(AsmErrorKind::UnknownMnemonic(mnemonic.to_string()), 0)),
     };
+    read_end(&mut iter)?;
 
-    instruction
+    Ok(Item::Instruction(instruction))
 }
 
-#[derive(Debug)]
-enum ReadError {
-    NoMoreChars,
-    UnexpectedChar(usize),
-}
-
-type ReadResult<T> = Result<T, ReadError>;
-
-trait ReadOrPanic<T> {
-    fn or_panic(self, line: usize) -> T;
+fn read_data(chars: &mut SlidingWindow) -> ReadResult<Item> {
+    let word = read_hex_word(chars)?;
+    read_end(chars)?;
+    Ok(Item::Data(word))
 }
 
-impl<T> ReadOrPanic<T> for ReadResult<T> {
-    fn or_panic(self, line: usize) -> T {
-        match self {
-            Ok(value) => value,
-            Err(err) => match err {
-                ReadError::NoMoreChars => panic!("Unexpected EOF."),
-                ReadError::UnexpectedChar(col) => {
-                    panic!("Unexpected character at {}:{}", line, col)
-                }
-            },
-        }
+/// Checks that nothing but whitespace is left
+fn read_end(chars: &mut SlidingWindow) -> ReadResult<()> {
+    eat_whitespace(chars);
+    match chars.peek() {
+        None => Ok(()),
+        Some(_) => Err(ReadError(AsmErrorKind::TrailingGarbage, chars.next_pos())),
     }
 }
 
@@ -139,8 +368,6 @@ struct WindowSource<'s> {
 #[derive(Debug)]
 struct SlidingWindow<'k, 's: 'k> {
     parent: &'k mut WindowSource<'s>,
-    start: usize,
-    last: usize,
 }
 
 impl<'s> WindowSource<'s> {
@@ -154,58 +381,39 @@ impl<'s> WindowSource<'s> {
     fn window(&mut self) -> SlidingWindow<'_, 's> {
         SlidingWindow {
             parent: self,
-            start: 0,
-            last: 0,
         }
     }
 }
 
 impl<'k, 's> SlidingWindow<'k, 's> {
-    fn window_from_here(&mut self) -> SlidingWindow<'_, 's> {
-        SlidingWindow {
-            parent: self.parent,
-            start: self.last,
-            last: self.last,
-        }
-    }
-
-    fn oob(&self, idx: usize) -> bool {
-        idx >= self.parent.source.len()
-    }
-
-    fn pos(&self) -> usize {
-        self.last
-    }
-
     fn next(&mut self) -> Option<char> {
-        if let Some((next_idx, next_chr)) = self.parent.chars.next() {
-            self.last = next_idx;
-            Some(next_chr)
-        } else {
-            None
-        }
+        self.parent.chars.next().map(|(_, c)| c)
     }
 
     fn peek(&mut self) -> Option<&char> {
         self.parent.chars.peek().map(|(_, c)| c)
     }
 
-    fn collect(&mut self) -> Option<&'s str> {
-        if self.oob(self.start) {
-            return None;
-        }
-        let slice = &self.parent.source[self.start..=self.last];
-        self.start = self.last + 1;
-        self.last = self.start;
-        Some(slice)
-    }
-
     fn take_while<F: Fn(&char) -> bool>(&mut self, pred: F) -> &mut Self {
         while self.peek().is_some() && pred(self.peek().unwrap()) {
             self.next();
         }
         self
     }
+
+    /// Index of the next char (or one-past-the-end)
+    fn next_pos(&mut self) -> usize {
+        let len = self.parent.source.len();
+        self.parent.chars.peek().map_or(len, |(idx, _)| *idx)
+    }
+
+    /// Consumes chars while `pred` holds, and returns them (possibly empty)
+    fn read_while<F: Fn(&char) -> bool>(&mut self, pred: F) -> &'s str {
+        let start = self.next_pos();
+        self.take_while(pred);
+        let end = self.next_pos();
+        &self.parent.source[start..end]
+    }
 }
 
 trait OptionalRead<T> {
@@ -219,24 +427,19 @@ impl<T> OptionalRead<T> for ReadResult<T> {
 }
 
 fn read_char(chars: &mut SlidingWindow) -> ReadResult<char> {
-    let next_char = chars.next();
-    if next_char.is_none() {
-        Err(ReadError::NoMoreChars)
-    } else {
-        Ok(next_char.unwrap())
+    match chars.next() {
+        Some(c) => Ok(c),
+        None => Err(ReadError::unexpected(chars)),
     }
 }
 
 fn match_char(to_match: char, chars: &mut SlidingWindow) -> ReadResult<()> {
-    let peek = chars.peek();
-    if peek.is_none() {
-        return Err(ReadError::NoMoreChars);
-    }
-    if *peek.unwrap() == to_match {
-        chars.next();
-        return Ok(());
-    } else {
-        return Err(ReadError::UnexpectedChar(chars.pos()));
+    match chars.peek() {
+        Some(c) if *c == to_match => {
+            chars.next();
+            Ok(())
+        }
+        _ => Err(ReadError::unexpected(chars)),
     }
 }
 
@@ -247,105 +450,162 @@ fn eat_whitespace<'r, 'k, 's>(
 }
 
 fn read_register(chars: &mut SlidingWindow) -> ReadResult<Register> {
+    let start = chars.next_pos();
+    let bad = ReadError(AsmErrorKind::BadOperand, start);
     let register = match read_char(chars)? {
         'a' => Register::A,
+        'f' => Register::F,
         'b' => match read_char(chars)? {
             'h' => Register::BH,
             'l' => Register::BL,
-            _ => return Err(ReadError::UnexpectedChar(chars.pos())),
+            _ => return Err(bad),
         },
         'c' => match read_char(chars)? {
             'h' => Register::CH,
             'l' => Register::CL,
-            _ => return Err(ReadError::UnexpectedChar(chars.pos())),
+            _ => return Err(bad),
         },
         'x' => Register::X,
-        's' => {
-            debug_assert!(read_char(chars)? == 'p');
-            unimplemented!("SP is not addressable")
-            //Register::SP
-        }
-        _ => return Err(ReadError::UnexpectedChar(chars.pos())),
+        's' => match (read_char(chars)?, read_char(chars)?) {
+            ('p', 'h') => Register::SPH,
+            ('p', 'l') => Register::SPL,
+            _ => return Err(bad),
+        },
+        _ => return Err(bad),
     };
     Ok(register)
 }
 
-fn read_hex_word(chars: &mut SlidingWindow) -> ReadResult<uWord> {
-    let mut read_hex_char = || {
-        let next = chars.peek();
-        if next.is_none() {
-            return Err(ReadError::NoMoreChars);
-        }
-        let next = next.unwrap();
-        if next.is_digit(16) {
-            Ok(chars.next().unwrap())
-        } else {
-            Err(ReadError::UnexpectedChar(chars.pos()))
-        }
-    };
+/// Parses a two-digit hex word
+fn parse_hex_word(literal: &str) -> Option<uWord> {
+    u8::from_str_radix(literal, 16).ok().and_then(|x| uWord::try_from(x).ok())
+}
 
+fn read_hex_word(chars: &mut SlidingWindow) -> ReadResult<uWord> {
+    let start = chars.next_pos();
+    let literal = chars.read_while(|c| c.is_ascii_hexdigit());
     // É high-word/low-word, mas não high char low char!
     // e.g. $abcd = 0xab + 2^6 × 0xcd
-    let high = read_hex_char()?;
-    let low = read_hex_char()?;
-
-    let high = (high.to_digit(16).unwrap() as u8) << 4;
-    let low = low.to_digit(16).unwrap() as u8;
+    match literal.len() {
+        0 => Err(ReadError::unexpected(chars)),
+        2 => parse_hex_word(literal)
+            .ok_or(ReadError(AsmErrorKind::OutOfRange(literal.to_string()), start)),
+        _ => Err(ReadError(AsmErrorKind::BadOperand, start)),
+    }
+}
 
-    let value = high + low;
+fn is_symbol_char(c: &char) -> bool {
+    c.is_alphanumeric() || *c == '_'
+}
 
-    Ok(uWord::try_from(value).unwrap())
+fn read_symbol<'s>(chars: &mut SlidingWindow<'_, 's>) -> ReadResult<&'s str> {
+    let symbol = chars.read_while(is_symbol_char);
+    if symbol.is_empty() {
+        Err(ReadError::unexpected(chars))
+    } else {
+        Ok(symbol)
+    }
 }
 
-fn read_address(chars: &mut SlidingWindow) -> ReadResult<Address> {
-    let lo = read_hex_word(chars)?;
-    let hi = read_hex_word(chars)?;
-    Ok(Address::from_hi_lo(hi, lo))
+/// Parses a literal address `llhh`. Returns `None` if it is not one, and 
+/// `Some(Err(()))` if it is one but out of range.
+pub fn parse_address(literal: &str) -> Option<Result<Address, ()>> {
+    if literal.len() != 4 || !literal.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None
+    }
+    match (parse_hex_word(&literal[0..2]), parse_hex_word(&literal[2..4])) {
+        (Some(lo), Some(hi)) => Some(Ok(Address::from_hi_lo(hi, lo))),
+        _ => Some(Err(())),
+    }
 }
 
-// Can be signed
-fn read_decimal(chars: &mut SlidingWindow) -> ReadResult<iWord> {
-    let mut subwindow = chars.window_from_here();
-    let value = subwindow
-        .take_while(|c| c.is_digit(10) || *c == '+' || *c == '-')
-        .collect()
-        .map_or(Err(ReadError::NoMoreChars), |s| Ok(s))?;
-    if value.is_empty() {
-        return Err(ReadError::UnexpectedChar(chars.pos()));
+/// Reads an address, given either as a label or as a literal `llhh` (labels 
+/// can't read as one)
+fn read_reference(chars: &mut SlidingWindow, context: Context) -> ReadResult<Address> {
+    let start = chars.next_pos();
+    let symbol = read_symbol(chars)?;
+    let label = context.symbols.and_then(|symbols| symbols.labels.get(symbol));
+    match (label, parse_address(symbol)) {
+        (Some(address), _) => Ok(*address),
+        (None, Some(Ok(address))) => Ok(address),
+        (None, Some(Err(()))) => Err(ReadError(AsmErrorKind::OutOfRange(symbol.to_string()), start)),
+        (None, None) => match context.symbols {
+            None => Ok(Address::ZERO),  // Placeholder, first pass
+            Some(_) => Err(ReadError(AsmErrorKind::UnknownLabel(symbol.to_string()), start)),
+        }
     }
-    let value = value.trim().parse::<i8>().unwrap();
-    Ok(iWord::try_from(value).unwrap())
 }
 
-fn read_decimal_long(chars: &mut SlidingWindow) -> ReadResult<iLong> {
-    let mut subwindow = chars.window_from_here();
-    let value = subwindow
-        .take_while(|c| c.is_digit(10) || *c == '+' || *c == '-')
-        .collect()
-        .map_or(Err(ReadError::NoMoreChars), |s| Ok(s))?;
-    if value.is_empty() {
-        return Err(ReadError::UnexpectedChar(chars.pos()));
+fn is_decimal_char(c: &char) -> bool {
+    c.is_ascii_digit() || *c == '+' || *c == '-'
+}
+
+/// Reads a branch target, given either as a label or as a signed number of 
+/// statements to skip (e.g. `+1` skips the next line, `-1` loops on itself), 
+/// and returns it as an offset in words from the next instruction. A raw 
+/// offset word can also be given as `#xx`.
+fn read_branch(chars: &mut SlidingWindow, context: Context) -> ReadResult<Offset> {
+    if match_char('#', chars).optional().is_some() {
+        return Ok(read_hex_word(chars)?.as_iword())
+    }
+    let start = chars.next_pos();
+    let is_count = matches!(chars.peek(), Some(c) if is_decimal_char(c));
+    let target = if is_count {
+        let literal = chars.read_while(is_decimal_char);
+        let count = literal.parse::<isize>()
+            .map_err(|_| ReadError(AsmErrorKind::BadOperand, start))?;
+        match context.symbols {
+            None => return Ok(Offset::ZERO),
+            Some(symbols) => {
+                let idx = context.statement as isize + 1 + count;
+                if idx < 0 || idx as usize >= symbols.statements.len() {
+                    return Err(ReadError(AsmErrorKind::BranchOutOfProgram, start))
+                }
+                symbols.statements[idx as usize]
+            }
+        }
+    } else {
+        read_reference(chars, context)?
+    };
+    match context.symbols {
+        None => Ok(Offset::ZERO),
+        Some(symbols) => {
+            let next = symbols.statements[context.statement + 1];
+            let offset = target.value() as isize - next.value() as isize;
+            i8::try_from(offset).ok()
+                .and_then(|x| Offset::try_from(x).ok())
+                .ok_or(ReadError(AsmErrorKind::BranchOutOfRange(offset), start))
+        }
     }
-    let value = value.trim().parse::<i16>().unwrap();
-    Ok(iLong::try_from(value).unwrap())
 }
 
-fn read_time(chars: &mut SlidingWindow) -> ReadResult<iLong> {
+fn read_time(chars: &mut SlidingWindow, context: Context) -> ReadResult<iLong> {
     match match_char('@', chars).optional() {
         None => Ok(iLong::ZERO),
         Some(_) => {
-            let _ = match_char('-', chars);
-            let _ = match_char('+', chars);
-            let value = read_decimal_long(chars)?;
-            dprintln!("qux {:?} {:?}", value, iLong::try_from(value).unwrap());
-            Ok(iLong::try_from(value).unwrap())
+            let start = chars.next_pos();
+            let literal = chars.read_while(is_decimal_char);
+            if literal.is_empty() {
+                return Err(ReadError::unexpected(chars));
+            }
+            let value = literal.parse::<i16>()
+                .map_err(|_| ReadError(AsmErrorKind::BadOperand, start))?;
+            let time = iLong::try_from(value)
+                .map_err(|_| ReadError(AsmErrorKind::OutOfRange(literal.to_string()), start))?;
+            if value.unsigned_abs() as usize > context.max_jump {
+                return Err(ReadError(AsmErrorKind::JumpOutOfRange(value as isize, context.max_jump), start))
+            }
+            Ok(time)
         }
     }
 }
 
-fn read_operand(chars: &mut SlidingWindow) -> ReadResult<Operand> {
-    let next = chars.peek().map_or(Err(ReadError::NoMoreChars), |x| Ok(x));
-    let operand = match next? {
+fn read_operand(chars: &mut SlidingWindow, context: Context) -> ReadResult<Operand> {
+    let next = match chars.peek() {
+        Some(c) => *c,
+        None => return Err(ReadError::unexpected(chars)),
+    };
+    let operand = match next {
         '#' => {
             match_char('#', chars)?;
             let word = read_hex_word(chars)?;
@@ -353,147 +613,287 @@ fn read_operand(chars: &mut SlidingWindow) -> ReadResult<Operand> {
         }
         '%' => {
             match_char('%', chars)?;
-            let lo = read_hex_word(chars)?;
-            let hi = read_hex_word(chars)?;
-            let op = Address::from_hi_lo(hi, lo);
+            let op = read_reference(chars, context)?;
             
             let next = chars.peek();
             if next.is_some() && *next.unwrap() == ',' {
                 match_char(',', chars)?;
                 match_char('x', chars)?;
 
-                let time = read_time(chars)?;
+                let time = read_time(chars, context)?;
                 Timed{op: Op::Abx(op), time: time}
             } else {
                 
-                let time = read_time(chars)?;
+                let time = read_time(chars, context)?;
                 Timed{op: Op::Abs(op), time: time}
             }
         }
         '(' => {
             match_char('(', chars)?;
-            let operand = match read_char(chars)? {
-                '%' => {
-                    let lo = read_hex_word(chars)?;
-                    let hi = read_hex_word(chars)?;
-                    let op = Address::from_hi_lo(hi, lo);
-                    let time = read_time(chars)?;
-
-                    Timed{op: Op::Ind(op), time: time}
+            let op = match chars.peek() {
+                Some('b') => { chars.next(); Op::Inr(Pair::B) }
+                Some('c') => { chars.next(); Op::Inr(Pair::C) }
+                _ => {
+                    match_char('%', chars)?;
+                    Op::Ind(read_reference(chars, context)?)
                 }
-                _ => unreachable!(), /*{
-                                         let register = read_register(chars)?;
-                                         let time = read_time(chars)?;
-                                         Operand::Reg(Timed{op: register, time: time)}
-                                     }*/
             };
+            let time = read_time(chars, context)?;
             match_char(')', chars)?;
-            operand
+            Timed{op: op, time: time}
         }
         _ => {
             let register = read_register(chars)?;
-            let time = read_time(chars)?;
+            let time = read_time(chars, context)?;
             Timed{op: Op::Reg(register), time: time}
         }
     };
     Ok(operand)
 }
 
-fn read_operands(chars: &mut SlidingWindow) -> ReadResult<Operands> {
-    let src = read_operand(chars)?;
+fn read_operands(chars: &mut SlidingWindow, context: Context) -> ReadResult<Operands> {
+    let src = read_operand(chars, context)?;
     eat_whitespace(chars);
-    let dst = read_operand(chars)?;
+    let dst = read_operand(chars, context)?;
     Ok(Operands{src, dst})
 }
 
-pub fn mnemonic(cmd: Instruction) -> String {
-    let mnemonic_op = |op: Op| -> String {
-        match op {
-            Op::Reg(r) => match r {
-                Register::A => "a",
-                Register::BH => "bh",
-                Register::BL => "bl",
-                Register::CH => "ch",
-                Register::CL => "cl",
-                Register::X => "x",
+// Disassembly //
+
+/// Decodes `range` of `ram` back into source, as `(address, instruction, 
+/// mnemonic)`. Words that do not form a valid instruction (or one that the 
+/// assembler would encode differently, or that runs past the end of `range`) 
+/// are emitted one by one as `dat xx`, with no instruction. Assembling the 
+/// mnemonics gives back the same words.
+pub fn disassemble(ram: &Ram, range: std::ops::Range<usize>) -> Vec<(Address, Option<Instruction>, String)> {
+    let mut m = Machine { ram: ram.clone(), ..Machine::new() };
+    let mut result = vec![];
+    let mut idx = range.start;
+    while idx < range.end {
+        let address = Address::try_from(idx as u16).unwrap();
+        m.cpu.pc = address;
+        let instruction = Instruction::decode(&mut m).ok().filter(|x| {
+            let end = usize::from(m.cpu.pc);
+            let mut encoded = Machine::new();
+            encoded.cpu.pc = address;
+            encoded.program(|m| x.encode(m));
+            idx < end && end <= range.end
+                && usize::from(encoded.cpu.pc) == end
+                && (idx..end).all(|i| encoded.ram[i] == m.ram[i])
+        });
+        match instruction {
+            Some(x) => {
+                result.push((address, Some(x.clone()), x.to_string()));
+                idx = usize::from(m.cpu.pc);
+            }
+            None => {
+                result.push((address, None, format!("dat {:02x}", m.ram[idx].value())));
+                idx += 1;
             }
-            .to_string(),
-            Op::Imm(v) => format!("#{}", v.value()),
-            Op::Abs(v) => format!("%{}", v.value()),
-            Op::Abx(v) => format!("%{},x", v.value()),
-            Op::Ind(v) => format!("(%{})", v.value()),
-        }
-    };
-    let mnemonic_timed_op = |op: Operand| -> String {
-        let time: i16 = op.time.into();
-        let op = op.op;
-        if time == 0 {
-            mnemonic_op(op)
-        } else {
-            format!("{}@{:+}", mnemonic_op(op), time)
         }
-    };
+    }
+    result
+}
 
-    match cmd {
-        Instruction::Mov(Operands { src, dst }) => {
-            format!("mov {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
-        }
-        Instruction::Psh(op) => format!("psh {}", mnemonic_timed_op(op)),
-        Instruction::Pop(op) => format!("pop {}", mnemonic_timed_op(op)),
-        Instruction::Add(Operands { src, dst }) => {
-            format!("add {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
-        }
-        Instruction::Sub(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
-        }
-        Instruction::Mul(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
-        }
-        Instruction::Muh(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::universe::MAX_JUMP;
+
+    fn addresses() -> Vec<Address> {
+        [0x000, 0x080, 0x3c5, 0xfff].iter().map(|x| Address::try_from(*x).unwrap()).collect()
+    }
+
+    fn times() -> Vec<iLong> {
+        [0, 1, -1, 4, -37, 2047, -2048].iter().map(|x| iLong::try_from(*x).unwrap()).collect()
+    }
+
+    /// Every addressing mode, with a sample of values and time offsets
+    fn operands() -> Vec<Operand> {
+        use Register::*;
+        let mut result = vec![];
+        for time in times() {
+            for r in [A, F, BH, BL, CH, CL, X, SPH, SPL] {
+                result.push(Timed { op: Op::Reg(r), time });
+            }
+            for x in addresses() {
+                result.push(Timed { op: Op::Abs(x), time });
+                result.push(Timed { op: Op::Abx(x), time });
+                result.push(Timed { op: Op::Ind(x), time });
+            }
+            for p in [Pair::B, Pair::C] {
+                result.push(Timed { op: Op::Inr(p), time });
+            }
         }
-        Instruction::Mus(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        for x in [0x00, 0x15, 0x3f] {
+            result.push(Timed { op: Op::Imm(uWord::lit(x)), time: iLong::ZERO });
         }
-        Instruction::Div(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        result
+    }
+
+    /// Every instruction, with every combination of sample operands
+    fn instructions() -> Vec<Instruction> {
+        use Instruction::*;
+        let mut result = vec![Clc, Sec, Ret, Nop, Hcf];
+        let operands = operands();
+        for op in operands.iter() {
+            for f in [Psh, Pop, Not, Lsl, Lsr, Asr, Inc, Dec] {
+                result.push(f(op.clone()));
+            }
+            for dst in operands.iter() {
+                let ops = Operands { src: op.clone(), dst: dst.clone() };
+                for f in [Mov, Xch, Add, Sub, Mul, Muh, Mus, Div, Dis, Mod, Mos, And, Or, Xor, Cmp, Bit] {
+                    result.push(f(ops.clone()));
+                }
+            }
         }
-        Instruction::Mod(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        for x in addresses() {
+            result.push(Jmp(x));
+            result.push(Cal(x));
         }
-        Instruction::And(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        for x in iWord::MIN.value()..=iWord::MAX.value() {
+            let x = iWord::try_from(x).unwrap();
+            for f in [Bcc, Bcs, Bne, Beq, Bpl, Bmi] {
+                result.push(f(x));
+            }
         }
-        Instruction::Or(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        result
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for instruction in instructions() {
+            let text = instruction.to_string();
+            let program = assemble(&text, MAX_JUMP).unwrap_or_else(|e| panic!("{}: {:?}", text, e));
+            assert_eq!(program.items, vec![Item::Instruction(instruction)], "{}", text);
         }
-        Instruction::Xor(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+    }
+
+    #[test]
+    fn jump_limit() {
+        assert!(assemble("mov %0001@+80 a", 80).is_ok());
+        let errors = assemble("nop\nmov a %0001@-81", 80).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, AsmErrorKind::JumpOutOfRange(-81, 80));
+        assert_eq!((errors[0].line, errors[0].column), (2, 13));
+    }
+
+    /// Number of words `source` assembles to
+    fn size(source: &str) -> isize {
+        assemble(source, MAX_JUMP).unwrap().items.iter().map(|x| x.size() as isize).sum()
+    }
+
+    #[test]
+    fn labels() {
+        let source = "jmp end\n:loop\nnop\nbne loop\n:end\ncal loop";
+        let program = assemble(source, MAX_JUMP).unwrap();
+        let address = |x: isize| Address::try_from((ORIGIN as isize + x) as u16).unwrap();
+        let (jmp, nop, bne) = (size("jmp 0000"), size("nop"), size("bne #00"));
+        assert_eq!(program.items, [
+            // Forward
+            Item::Instruction(Instruction::Jmp(address(jmp + nop + bne))),
+            Item::Instruction(Instruction::Nop),
+            // Backward, from the next instruction
+            Item::Instruction(Instruction::Bne(Offset::try_from(-(nop + bne) as i8).unwrap())),
+            Item::Instruction(Instruction::Cal(address(jmp))),
+        ]);
+        // As many statements as there are in between, labels included
+        let counted = assemble("jmp end\n:loop\nnop\nbne -2\n:end\ncal loop", MAX_JUMP).unwrap();
+        assert_eq!(counted.items, program.items);
+    }
+
+    #[test]
+    fn label_errors() {
+        let errors = assemble(":a\nnop\n:a\njmp b", MAX_JUMP).unwrap_err();
+        let kinds: Vec<_> = errors.iter().map(|x| (x.line, x.kind.clone())).collect();
+        assert_eq!(kinds, [
+            (3, AsmErrorKind::DuplicateLabel("a".to_string())),
+            (4, AsmErrorKind::UnknownLabel("b".to_string())),
+        ]);
+        // `jmp 0102` could mean either
+        for label in ["0102", "beef"] {
+            let errors = assemble(&format!(":{}\nnop", label), MAX_JUMP).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!((errors[0].line, &errors[0].kind), (1, &AsmErrorKind::AddressLabel(label.to_string())));
         }
-        Instruction::Not(op) => format!("not {}", mnemonic_timed_op(op)),
-        Instruction::Lsl(op) => format!("lsl {}", mnemonic_timed_op(op)),
-        Instruction::Lsr(op) => format!("lsr {}", mnemonic_timed_op(op)),
-        Instruction::Asr(op) => format!("asr {}", mnemonic_timed_op(op)),
-        Instruction::Inc(op) => format!("inc {}", mnemonic_timed_op(op)),
-        Instruction::Dec(op) => format!("dec {}", mnemonic_timed_op(op)),
-        Instruction::Cmp(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+        assert!(assemble(":beefy\njmp beefy", MAX_JUMP).is_ok());
+    }
+
+    #[test]
+    fn branch_range() {
+        let (nop, bne) = (size("nop"), size("bne #00"));
+        // An Offset reaches back 32 words, from the next instruction
+        let reach = (32 - bne) / nop;
+        let source = |n| format!(":start\n{}bne start", "nop\n".repeat(n as usize));
+        assert!(assemble(&source(reach), MAX_JUMP).is_ok());
+        let errors = assemble(&source(reach + 1), MAX_JUMP).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let offset = -((reach + 1) * nop + bne);
+        assert_eq!(errors[0].kind, AsmErrorKind::BranchOutOfRange(offset));
+        assert_eq!((errors[0].line, errors[0].column), (reach as usize + 3, 5));
+        assert_eq!(assemble("bne +1", MAX_JUMP).unwrap_err()[0].kind, AsmErrorKind::BranchOutOfProgram);
+    }
+
+    #[test]
+    fn errors() {
+        let source = "nop\nfoo a\n\tmov #40 a ; comment\nmov a\n:x\nhcf b\nmov #01 a\njmp nowhere\n";
+        let errors = assemble(source, MAX_JUMP).unwrap_err();
+        let error = |kind, line: usize, column, snippet: &str| AsmError { kind, line, column, snippet: snippet.to_string() };
+        assert_eq!(errors, [
+            error(AsmErrorKind::UnknownMnemonic("foo".to_string()), 2, 1, "foo a"),
+            error(AsmErrorKind::OutOfRange("40".to_string()), 3, 7, "\tmov #40 a ; comment"),
+            error(AsmErrorKind::MissingOperand, 4, 6, "mov a"),
+            error(AsmErrorKind::TrailingGarbage, 6, 5, "hcf b"),
+            error(AsmErrorKind::UnknownLabel("nowhere".to_string()), 8, 5, "jmp nowhere"),
+        ]);
+        // The caret lines up under the tab
+        assert_eq!(errors[1].to_string(), "error at 3:7: literal `40` out of range\n    \tmov #40 a ; comment\n    \t     ^");
+    }
+
+    /// The words `source` assembles to, from the origin
+    fn image(source: &str) -> Vec<uWord> {
+        let mut m = Machine::new();
+        assemble_into(&mut m, source, MAX_JUMP).unwrap();
+        m.ram.read(ORIGIN..m.map.eprom_end)
+    }
+
+    /// Disassembles `words` at the origin, and assembles the result again
+    fn reassemble(words: &[uWord]) -> (String, Vec<uWord>) {
+        let mut ram = Ram::default();
+        ram.write(ORIGIN, words);
+        let lines: Vec<_> = disassemble(&ram, ORIGIN..ORIGIN + words.len()).into_iter().map(|x| x.2).collect();
+        let text = lines.join("\n");
+        let words = image(&text);
+        (text, words)
+    }
+
+    #[test]
+    fn disassembly_round_trip() {
+        for source in [include_str!("../examples/bubble_time.asm"), include_str!("../examples/bench.asm")] {
+            let words = image(&source.to_lowercase());
+            assert_eq!(reassemble(&words).1, words);
         }
-        Instruction::Bit(Operands { src, dst }) => {
-            format!(" {} {}", mnemonic_timed_op(src), mnemonic_timed_op(dst))
+    }
+
+    #[test]
+    fn disassembly_data() {
+        // A bad opcode, a bad register, a bad mode, and a jump cut short
+        let words = image("dat 05\nnop\ndat 01\ndat 00\ndat 3f\ndat 00\nmov #01 a\ndat 01\ndat 3f\nhcf\ndat 30\ndat 01");
+        let (text, again) = reassemble(&words);
+        assert_eq!(again, words);
+        // Words after a bad one are decoded afresh
+        assert_eq!(text, "dat 05\nnop\ndat 01\nnop\nret\nnop\nmov #01 a\ndat 01\nret\nhcf\ndat 30\ndat 01");
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        for instruction in instructions() {
+            let mut m = Machine::new();
+            m.program(|m| instruction.encode(m));
+            let end = m.cpu.pc;
+            m.reset_cpu();
+            assert_eq!(Instruction::decode(&mut m), Ok(instruction.clone()));
+            assert_eq!(m.cpu.pc, end, "{}", instruction);
         }
-        Instruction::Jmp(op) => format!("jmp %{}", radix(op.value(), 16)),
-        Instruction::Bcc(op) => format!("bcc #{}", radix(op.value(), 16)),
-        Instruction::Bcs(op) => format!("bcs #{}", radix(op.value(), 16)),
-        Instruction::Bne(op) => format!("bne #{}", radix(op.value(), 16)),
-        Instruction::Beq(op) => format!("beq #{}", radix(op.value(), 16)),
-        Instruction::Bpl(op) => format!("bpl #{}", radix(op.value(), 16)),
-        Instruction::Bmi(op) => format!("bmi #{}", radix(op.value(), 16)),
-        Instruction::Clc => "clc".to_string(),
-        Instruction::Sec => "sec".to_string(),
-        Instruction::Cal(op) => format!("cal #{}", radix(op.value(), 16)),
-        Instruction::Ret => "ret".to_string(),
-        Instruction::Nop => "nop".to_string(),
-        Instruction::Hcf => "hcf".to_string(),
     }
 }
@@ -0,0 +1,158 @@
+//! Read-only queries on the states kept in the timeline, for debugging and
+//! visualisation
+
+use crate::prelude::*;
+use crate::interpreter::operand_read_inner;
+use crate::universe::Timeline;
+
+/// The addressable registers
+pub const REGISTERS: [Register; 9] = {
+    use Register::*;
+    [A, F, BH, BL, CH, CL, X, SPH, SPL]
+};
+
+/// Parses a location to inspect: a register (`a`, `bh`, ...) or an address
+/// (`%llhh`)
+pub fn parse_location(literal: &str) -> Option<Op> {
+    let literal = literal.trim().to_lowercase();
+    match literal.strip_prefix('%') {
+        Some(address) => crate::assembler::parse_address(address).and_then(Result::ok).map(Op::Abs),
+        None => REGISTERS.iter().find(|x| x.to_string() == literal).cloned().map(Op::Reg),
+    }
+}
+
+/// How two states differ
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StateDiff {
+    /// Registers that differ, with their values in the first state and in the
+    /// second
+    pub registers: Vec<(Register, uWord, uWord)>,
+    pub pc: Option<(Address, Address)>,
+    /// Addresses that differ, likewise
+    pub ram: Vec<(Address, uWord, uWord)>,
+}
+
+impl StateDiff {
+    pub fn between(a: &Machine, b: &Machine) -> Self {
+        let registers = REGISTERS.iter()
+            .map(|x| (x.clone(), operand_read_inner(a, &Op::Reg(x.clone())), operand_read_inner(b, &Op::Reg(x.clone()))))
+            .filter(|(_, x, y)| x != y)
+            .collect();
+        let pc = (a.cpu.pc != b.cpu.pc).then_some((a.cpu.pc, b.cpu.pc));
+        let ram = a.ram.changes(&b.ram)
+            .map(|i| (Address::try_from(i as u16).unwrap(), a.ram[i], b.ram[i]))
+            .collect();
+        StateDiff { registers, pc, ram }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.pc.is_none() && self.ram.is_empty()
+    }
+}
+
+impl std::fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (register, x, y) in &self.registers {
+            writeln!(f, "{}: {:02x} -> {:02x}", register, x.value(), y.value())?;
+        }
+        if let Some((x, y)) = self.pc {
+            writeln!(f, "pc: {:02x}{:02x} -> {:02x}{:02x}", x.hi().value(), x.lo().value(), y.hi().value(), y.lo().value())?;
+        }
+        for (address, x, y) in &self.ram {
+            writeln!(f, "{}: {:02x} -> {:02x}", Op::Abs(*address), x.value(), y.value())?;
+        }
+        Ok(())
+    }
+}
+
+/// A read-only view of the states kept in the timeline, from the oldest one
+/// not yet final to the last one computed. States past the present are left
+/// over from before a rewind, and may yet be computed again.
+#[derive(Debug, Clone, Copy)]
+pub struct TimelineView<'a> {
+    timeline: &'a Timeline,
+    present: usize,
+}
+
+impl<'a> TimelineView<'a> {
+    pub fn new(timeline: &'a Timeline, present: usize) -> Self {
+        TimelineView { timeline, present }
+    }
+
+    /// Times of the states in view
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.timeline.ti()..self.timeline.tf()
+    }
+
+    /// Time of the state being computed
+    pub fn present(&self) -> usize {
+        self.present
+    }
+
+    /// The state at `t`, if in view
+    pub fn state_at(&self, t: usize) -> Option<&'a Machine> {
+        match self.range().contains(&t) {
+            true => Some(&self.timeline[t]),
+            false => None,
+        }
+    }
+
+    /// Value of `op` in each state in view, oldest first
+    pub fn history(&self, op: Op) -> impl DoubleEndedIterator<Item = (usize, uWord)> + ExactSizeIterator + 'a {
+        let timeline = self.timeline;
+        self.range().map(move |t| (t, operand_read_inner(&timeline[t], &op)))
+    }
+
+    /// Times at which `op` changed from the state before, with the new value,
+    /// oldest first (the oldest state in view has nothing to change from)
+    pub fn changes(&self, op: Op) -> impl DoubleEndedIterator<Item = (usize, uWord)> + 'a {
+        let next = self.history(op.clone()).skip(1);
+        self.history(op).zip(next)
+            .filter_map(|((_, before), (t, value))| (value != before).then_some((t, value)))
+    }
+
+    pub fn first_change(&self, op: Op) -> Option<usize> {
+        self.changes(op).next().map(|(t, _)| t)
+    }
+
+    pub fn last_change(&self, op: Op) -> Option<usize> {
+        self.changes(op).next_back().map(|(t, _)| t)
+    }
+
+    /// How the state at `b` differs from the one at `a`, if both are in view
+    pub fn diff(&self, a: usize, b: usize) -> Option<StateDiff> {
+        Some(StateDiff::between(self.state_at(a)?, self.state_at(b)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_into;
+    use crate::interpreter::step_one;
+    use crate::universe::MAX_JUMP;
+
+    #[test]
+    fn timeline_view() {
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), "mov #05 a\nmov #06 %0001\nmov #07 a\nhcf", MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        for _ in 0..4 { step_one(&mut universe, &mut modules).unwrap(); }
+        let view = universe.view();
+        assert_eq!(view.range(), 0..5);
+        assert!(view.state_at(5).is_none());
+        let a = Op::Reg(Register::A);
+        let history: Vec<_> = view.history(a.clone()).map(|(_, x)| x.value()).collect();
+        assert_eq!(history, [0, 5, 5, 7, 7]);
+        assert_eq!(view.changes(a.clone()).collect::<Vec<_>>(), [(1, uWord::lit(5)), (3, uWord::lit(7))]);
+        assert_eq!((view.first_change(a.clone()), view.last_change(a)), (Some(1), Some(3)));
+        let address = Address::try_from(0x40).unwrap();
+        assert_eq!(parse_location("%0001"), Some(Op::Abs(address)));
+        assert_eq!(view.last_change(Op::Abs(address)), Some(2));
+        let diff = view.diff(1, 3).unwrap();
+        assert_eq!(diff.registers, [(Register::A, uWord::lit(5), uWord::lit(7))]);
+        assert_eq!(diff.ram, [(address, uWord::ZERO, uWord::lit(6))]);
+        assert!(view.diff(3, 4).unwrap().pc.is_some());
+        assert!(view.diff(0, 5).is_none());
+    }
+}
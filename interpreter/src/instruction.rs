@@ -1,23 +1,36 @@
 use super::prelude::*;
 
 /// Addressable registers
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Register {
     A,
-    // F,
+    /// Flags (read-only)
+    F,
     BH,
     BL,
     CH,
     CL,
     X,
-    // SP,
+    /// Stack pointer, high word
+    SPH,
+    /// Stack pointer, low word
+    SPL,
+}
+
+/// Register pairs, high word and low word
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pair {
+    /// BH:BL
+    B,
+    /// CH:CL
+    C,
 }
 
 /// A time jump offset
 type TimeOffset = iLong;
 
 /// An operand T at a given time offset
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Timed<T> {
     pub op: T,
     /// Relative time of operation (wrt. call)
@@ -25,7 +38,7 @@ pub struct Timed<T> {
 }
 
 /// The basic addressing modes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Op {
     /// Named register
     Reg(Register),
@@ -39,15 +52,22 @@ pub enum Op {
     Abx(Address),
     /// Indirect access (address is value at that address, lo hi order)
     Ind(Address),
-    /*/// Indirect access (address is value at that register, lo hi order)
-    Inr(Timed<Register>),*/
+    /// Indirect access (address is value of that register pair)
+    Inr(Pair),
+}
+
+impl Op {
+    /// Whether writing to this operand is a fault
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, Op::Imm(_) | Op::Reg(Register::F))
+    }
 }
 
 /// The argument of an instruction that takes one operand
 pub type Operand = Timed<Op>;
 
 /// The argument of an instruction that takes two operands
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Operands {
     pub src: Operand,
     pub dst: Operand,
@@ -58,10 +78,10 @@ pub type Offset = iWord;
 
 //
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
     Mov(Operands),
-    /*Xch(Operands),*/
+    Xch(Operands),
     Psh(Operand),
     Pop(Operand),
 
@@ -71,9 +91,9 @@ pub enum Instruction {
     Muh(Operands),
     Mus(Operands),
     Div(Operands),
-    /*Dis(Operands),*/
+    Dis(Operands),
     Mod(Operands),
-    /*Mos(Operands),*/
+    Mos(Operands),
 
     And(Operands),
     Or(Operands),
@@ -110,18 +130,58 @@ pub enum Instruction {
 
 //
 
-fn read_register(m: &mut Machine) -> Register {
+/// Words that do not form a valid instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// No instruction has this opcode
+    Opcode(uWord),
+    /// No register has this code
+    Register(uWord),
+    /// Addressing mode out of range
+    Mode(uWord),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Opcode(x) => write!(f, "illegal opcode {:02x}", x.value()),
+            DecodeError::Register(x) => write!(f, "illegal register {:02x}", x.value()),
+            DecodeError::Mode(x) => write!(f, "illegal addressing mode {:02x}", x.value()),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// 6 modes × 6 modes × time flag do not fit in a mode word (5 bits for the 
+// modes, 1 for the flag), so register-indirect shares the register mode, and
+// is told apart by its code: 0x10 for (b), 0x11 for (c). The 5×5 packing of 
+// `Operands` is unchanged.
+
+/// Reads the code that follows a register mode: a register, or a pair for 
+/// register-indirect access
+fn read_register_op(m: &mut Machine) -> Result<Op, DecodeError> {
+    let code = m.read_pc();
+    match code.value() {
+        0x10 => Ok(Op::Inr(Pair::B)),
+        0x11 => Ok(Op::Inr(Pair::C)),
+        _ => register_from_code(code).map(Op::Reg),
+    }
+}
+
+fn register_from_code(code: uWord) -> Result<Register, DecodeError> {
     use Register::*;
-    match m.read_pc().value() {
-        0x0 => A,
-        /*0x1 => F,*/
-        0x2 => BH,
-        0x3 => BL,
-        0x4 => CH,
-        0x5 => CL,
-        0x6 => X,
-        /*0x7 => SP,*/
-        _ => unreachable!(),
+    match code.value() {
+        0x0 => Ok(A),
+        0x1 => Ok(F),
+        0x2 => Ok(BH),
+        0x3 => Ok(BL),
+        0x4 => Ok(CH),
+        0x5 => Ok(CL),
+        0x6 => Ok(X),
+        0x7 => Ok(SPH),
+        0x8 => Ok(SPL),
+        _ => Err(DecodeError::Register(code)),
     }
 }
 
@@ -151,13 +211,21 @@ fn write_register(m: &mut Machine, x: &Register) {
     use Register::*;
     match x {
         A  => m.write_pc(uWord::lit(0x0)),
-        /*F  => m.write_pc(uWord::lit(0x1)),*/
+        F  => m.write_pc(uWord::lit(0x1)),
         BH => m.write_pc(uWord::lit(0x2)),
         BL => m.write_pc(uWord::lit(0x3)),
         CH => m.write_pc(uWord::lit(0x4)),
         CL => m.write_pc(uWord::lit(0x5)),
         X  => m.write_pc(uWord::lit(0x6)),
-        /*SP => m.write_pc(uWord::lit(0x7)),*/
+        SPH => m.write_pc(uWord::lit(0x7)),
+        SPL => m.write_pc(uWord::lit(0x8)),
+    }
+}
+
+fn write_pair(m: &mut Machine, x: &Pair) {
+    match x {
+        Pair::B => m.write_pc(uWord::lit(0x10)),
+        Pair::C => m.write_pc(uWord::lit(0x11)),
     }
 }
 
@@ -182,40 +250,46 @@ fn write_time(m: &mut Machine, x: &TimeOffset) {
 // 
 
 impl Operand {
-    fn decode(m: &mut Machine, mode: uWord) -> Self {
+    fn decode(m: &mut Machine, mode: uWord) -> Result<Self, DecodeError> {
         use Op::*;
         let time_flag = mode.value() & 0b100000 != 0;
         let operand_flag = mode.value() & 0b011111;
-        match time_flag {
+        let operand = match time_flag {
             true => match operand_flag {
-                0x0 => Timed { op: Reg(read_register(m)), time: read_time(m) },
+                0x0 => Timed { op: read_register_op(m)?,  time: read_time(m) },
                 0x1 => Timed { op: Abs(read_address(m)),  time: read_time(m) },
                 0x2 => Timed { op: Ind(read_address(m)),  time: read_time(m) },
                 0x3 => Timed { op: Abx(read_address(m)),  time: read_time(m) },
                 0x4 => Timed { op: Imm(read_word(m)),     time: iLong::ZERO  },
-                _ => unreachable!(),
+                _ => return Err(DecodeError::Mode(mode)),
             }
             false => match operand_flag {
-                0x0 => Timed { op: Reg(read_register(m)), time: iLong::ZERO },
+                0x0 => Timed { op: read_register_op(m)?,  time: iLong::ZERO },
                 0x1 => Timed { op: Abs(read_address(m)),  time: iLong::ZERO },
                 0x2 => Timed { op: Ind(read_address(m)),  time: iLong::ZERO },
                 0x3 => Timed { op: Abx(read_address(m)),  time: iLong::ZERO },
                 0x4 => Timed { op: Imm(read_word(m)),     time: iLong::ZERO },
-                _ => unreachable!(),
+                _ => return Err(DecodeError::Mode(mode)),
             }
-        }
+        };
+        Ok(operand)
     }
 
     fn encode(m: &mut Machine, x: &Operand) -> () {
         use Op::*;
         let time_flag = x.time != iLong::ZERO;
-        let mode = |a,b| uWord::try_from((a as u8) | (b as u8)).unwrap();
+        let mode = |a,b| uWord::try_from((a as u8) | ((b as u8) << 5)).unwrap();
         match &x.op {
             Reg(y) => {
                 m.write_pc(mode(0x0, time_flag));
                 write_register(m, &y);
                 if time_flag { write_time(m, &x.time) };
             }
+            Inr(y) => {
+                m.write_pc(mode(0x0, time_flag));
+                write_pair(m, y);
+                if time_flag { write_time(m, &x.time) };
+            }
             Abs(y) => {
                 m.write_pc(mode(0x1, time_flag));
                 write_address(m, &y);
@@ -240,13 +314,15 @@ impl Operand {
 }
 
 impl Operands {
-    fn decode(m: &mut Machine, mode: uWord) -> Self {
-        let time_mode = (mode.value() & 0b100000);
+    fn decode(m: &mut Machine, mode: uWord) -> Result<Self, DecodeError> {
+        let time_mode = mode.value() & 0b100000;
+        // Only 5×5 of the 32 values are valid
+        if mode.value() & 0b011111 >= 0x5 * 0x5 { return Err(DecodeError::Mode(mode)) };
         let src_mode  = (mode.value() & 0b011111) % 0x5;
         let dst_mode  = (mode.value() & 0b011111) / 0x5;
-        let src = Operand::decode(m, uWord::try_from(src_mode | time_mode).unwrap());
-        let dst = Operand::decode(m, uWord::try_from(dst_mode | time_mode).unwrap());
-        Operands { src, dst }
+        let src = Operand::decode(m, uWord::try_from(src_mode | time_mode).unwrap())?;
+        let dst = Operand::decode(m, uWord::try_from(dst_mode | time_mode).unwrap())?;
+        Ok(Operands { src, dst })
     }
 
     fn encode(m: &mut Machine, x: &Operands) -> () {
@@ -256,6 +332,7 @@ impl Operands {
         if time_flag { mode |= 0b100000 };
         match x.src.op {
             Reg(_) => mode += 0x0,
+            Inr(_) => mode += 0x0,
             Abs(_) => mode += 0x1,
             Ind(_) => mode += 0x2,
             Abx(_) => mode += 0x3,
@@ -263,6 +340,7 @@ impl Operands {
         };
         match x.dst.op {
             Reg(_) => mode += 0x0 * 0x5,
+            Inr(_) => mode += 0x0 * 0x5,
             Abs(_) => mode += 0x1 * 0x5,
             Ind(_) => mode += 0x2 * 0x5,
             Abx(_) => mode += 0x3 * 0x5,
@@ -271,6 +349,7 @@ impl Operands {
         m.write_pc(uWord::try_from(mode).unwrap());
         match &x.src.op {
             Reg(y) => { write_register(m, &y); if time_flag { write_time(m, &x.src.time) } }
+            Inr(y) => { write_pair(m, y);      if time_flag { write_time(m, &x.src.time) } }
             Abs(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.src.time) } }
             Ind(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.src.time) } }
             Abx(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.src.time) } }
@@ -278,6 +357,7 @@ impl Operands {
         };
         match &x.dst.op {
             Reg(y) => { write_register(m, &y); if time_flag { write_time(m, &x.dst.time) } }
+            Inr(y) => { write_pair(m, y);      if time_flag { write_time(m, &x.dst.time) } }
             Abs(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.dst.time) } }
             Ind(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.dst.time) } }
             Abx(y) => { write_address(m, &y);  if time_flag { write_time(m, &x.dst.time) } }
@@ -287,34 +367,38 @@ impl Operands {
 }
 
 impl Instruction {
-    pub fn decode(m: &mut Machine) -> Self {
+    /// Reads an instruction at PC, and advances the PC past it. On an invalid 
+    /// instruction the PC is left somewhere past the opcode.
+    pub fn decode(m: &mut Machine) -> Result<Self, DecodeError> {
         use Instruction::*;
         let opcode = m.read_pc();
         /*let mode = m.read_pc();*/
-        match opcode.value() {
+        let instruction = match opcode.value() {
             0x00 => Nop, 
-            0x01 => { let mode = m.read_pc(); Mov(Operands::decode(m, mode)) },
-            /*0x02 => { let mode = m.read_pc(); Xch(Operands::decode(m, mode)) },*/
-            0x03 => { let mode = m.read_pc(); Psh(Operand::decode(m, mode))  },
-            0x04 => { let mode = m.read_pc(); Pop(Operand::decode(m, mode))  },
-            0x10 => { let mode = m.read_pc(); Add(Operands::decode(m, mode)) },
-            0x11 => { let mode = m.read_pc(); Sub(Operands::decode(m, mode)) },
-            0x12 => { let mode = m.read_pc(); Mul(Operands::decode(m, mode)) },
-            0x13 => { let mode = m.read_pc(); Muh(Operands::decode(m, mode)) },
-            0x14 => { let mode = m.read_pc(); Mus(Operands::decode(m, mode)) },
-            0x15 => { let mode = m.read_pc(); Div(Operands::decode(m, mode)) },
-            0x16 => { let mode = m.read_pc(); Mod(Operands::decode(m, mode)) },
-            0x17 => { let mode = m.read_pc(); And(Operands::decode(m, mode)) },
-            0x18 => { let mode = m.read_pc(); Or (Operands::decode(m, mode)) },
-            0x19 => { let mode = m.read_pc(); Xor(Operands::decode(m, mode)) },
-            0x1a => { let mode = m.read_pc(); Not(Operand::decode(m, mode))  },
-            0x1b => { let mode = m.read_pc(); Lsl(Operand::decode(m, mode))  },
-            0x1c => { let mode = m.read_pc(); Lsr(Operand::decode(m, mode))  },
-            0x1d => { let mode = m.read_pc(); Asr(Operand::decode(m, mode))  },
-            0x1e => { let mode = m.read_pc(); Inc(Operand::decode(m, mode))  },
-            0x1f => { let mode = m.read_pc(); Dec(Operand::decode(m, mode))  },
-            0x20 => { let mode = m.read_pc(); Cmp(Operands::decode(m, mode)) },
-            0x21 => { let mode = m.read_pc(); Bit(Operands::decode(m, mode)) },
+            0x01 => { let mode = m.read_pc(); Mov(Operands::decode(m, mode)?) },
+            0x02 => { let mode = m.read_pc(); Xch(Operands::decode(m, mode)?) },
+            0x03 => { let mode = m.read_pc(); Psh(Operand::decode(m, mode)?)  },
+            0x04 => { let mode = m.read_pc(); Pop(Operand::decode(m, mode)?)  },
+            0x10 => { let mode = m.read_pc(); Add(Operands::decode(m, mode)?) },
+            0x11 => { let mode = m.read_pc(); Sub(Operands::decode(m, mode)?) },
+            0x12 => { let mode = m.read_pc(); Mul(Operands::decode(m, mode)?) },
+            0x13 => { let mode = m.read_pc(); Muh(Operands::decode(m, mode)?) },
+            0x14 => { let mode = m.read_pc(); Mus(Operands::decode(m, mode)?) },
+            0x15 => { let mode = m.read_pc(); Div(Operands::decode(m, mode)?) },
+            0x16 => { let mode = m.read_pc(); Mod(Operands::decode(m, mode)?) },
+            0x17 => { let mode = m.read_pc(); And(Operands::decode(m, mode)?) },
+            0x18 => { let mode = m.read_pc(); Or (Operands::decode(m, mode)?) },
+            0x19 => { let mode = m.read_pc(); Xor(Operands::decode(m, mode)?) },
+            0x1a => { let mode = m.read_pc(); Not(Operand::decode(m, mode)?)  },
+            0x1b => { let mode = m.read_pc(); Lsl(Operand::decode(m, mode)?)  },
+            0x1c => { let mode = m.read_pc(); Lsr(Operand::decode(m, mode)?)  },
+            0x1d => { let mode = m.read_pc(); Asr(Operand::decode(m, mode)?)  },
+            0x1e => { let mode = m.read_pc(); Inc(Operand::decode(m, mode)?)  },
+            0x1f => { let mode = m.read_pc(); Dec(Operand::decode(m, mode)?)  },
+            0x20 => { let mode = m.read_pc(); Cmp(Operands::decode(m, mode)?) },
+            0x21 => { let mode = m.read_pc(); Bit(Operands::decode(m, mode)?) },
+            0x22 => { let mode = m.read_pc(); Dis(Operands::decode(m, mode)?) },
+            0x23 => { let mode = m.read_pc(); Mos(Operands::decode(m, mode)?) },
             0x30 => Jmp(read_address(m)),
             0x31 => Bcc(read_offset(m)),
             0x32 => Bcs(read_offset(m)),
@@ -327,8 +411,9 @@ impl Instruction {
             0x39 => Sec,
             0x3e => Hcf,
             0x3f => Ret,
-            _ => panic!("Invalid instruction {:x} at {:x}.", opcode.value(), m.cpu.pc.value() - 1),
-        }
+            _ => return Err(DecodeError::Opcode(opcode)),
+        };
+        Ok(instruction)
     }
 
     pub fn encode(&self, m: &mut Machine) {
@@ -340,7 +425,7 @@ impl Instruction {
             Hcf => { m.write_pc(uWord::lit(0x3e)) }
             Ret => { m.write_pc(uWord::lit(0x3f)) }
             Mov(ops) => { m.write_pc(uWord::lit(0x01)); Operands::encode(m, ops)}
-            /*Xch(ops) => { m.write_pc(uWord::lit(0x02)); Operands::encode(m, ops)}*/
+            Xch(ops) => { m.write_pc(uWord::lit(0x02)); Operands::encode(m, ops)}
             Psh(op) => { m.write_pc(uWord::lit(0x03)); Operand::encode(m, op)}
             Pop(op) => { m.write_pc(uWord::lit(0x04)); Operand::encode(m, op)}
             Add(ops) => { m.write_pc(uWord::lit(0x10)); Operands::encode(m, ops)}
@@ -361,6 +446,8 @@ impl Instruction {
             Dec(op) => { m.write_pc(uWord::lit(0x1f)); Operand::encode(m, op)}
             Cmp(op) => { m.write_pc(uWord::lit(0x20)); Operands::encode(m, op)}
             Bit(op) => { m.write_pc(uWord::lit(0x21)); Operands::encode(m, op)}
+            Dis(ops) => { m.write_pc(uWord::lit(0x22)); Operands::encode(m, ops)}
+            Mos(ops) => { m.write_pc(uWord::lit(0x23)); Operands::encode(m, ops)}
             Jmp(x) => { m.write_pc(uWord::lit(0x30)); write_address(m, x)}
             Bcc(x) => { m.write_pc(uWord::lit(0x31)); write_offset(m, x)}
             Bcs(x) => { m.write_pc(uWord::lit(0x32)); write_offset(m, x)}
@@ -372,3 +459,139 @@ impl Instruction {
         }
     }
 }
+
+// Pretty-printing, in the syntax read by the assembler
+
+/// Address as `llhh`
+struct Hex(Address);
+
+impl std::fmt::Display for Hex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02x}{:02x}", self.0.lo().value(), self.0.hi().value())
+    }
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Register::*;
+        let name = match self {
+            A  => "a",
+            F  => "f",
+            BH => "bh",
+            BL => "bl",
+            CH => "ch",
+            CL => "cl",
+            X  => "x",
+            SPH => "sph",
+            SPL => "spl",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::fmt::Display for Pair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pair::B => write!(f, "b"),
+            Pair::C => write!(f, "c"),
+        }
+    }
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Op::*;
+        match self {
+            Reg(x) => write!(f, "{}", x),
+            Imm(x) => write!(f, "#{:02x}", x.value()),
+            Abs(x) => write!(f, "%{}", Hex(*x)),
+            Abx(x) => write!(f, "%{},x", Hex(*x)),
+            Ind(x) => write!(f, "(%{})", Hex(*x)),
+            Inr(x) => write!(f, "({})", x),
+        }
+    }
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.op {
+            _ if self.time == TimeOffset::ZERO => write!(f, "{}", self.op),
+            // The time offset goes inside the parentheses
+            Op::Ind(x) => write!(f, "(%{}@{:+})", Hex(*x), self.time.value()),
+            Op::Inr(x) => write!(f, "({}@{:+})", x, self.time.value()),
+            _ => write!(f, "{}@{:+}", self.op, self.time.value()),
+        }
+    }
+}
+
+impl std::fmt::Display for Operands {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.src, self.dst)
+    }
+}
+
+impl Instruction {
+    pub fn name(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            Mov(_) => "mov",
+            Xch(_) => "xch",
+            Psh(_) => "psh",
+            Pop(_) => "pop",
+            Add(_) => "add",
+            Sub(_) => "sub",
+            Mul(_) => "mul",
+            Muh(_) => "muh",
+            Mus(_) => "mus",
+            Div(_) => "div",
+            Dis(_) => "dis",
+            Mod(_) => "mod",
+            Mos(_) => "mos",
+            And(_) => "and",
+            Or (_) => "or",
+            Xor(_) => "xor",
+            Not(_) => "not",
+            Lsl(_) => "lsl",
+            Lsr(_) => "lsr",
+            Asr(_) => "asr",
+            Inc(_) => "inc",
+            Dec(_) => "dec",
+            Cmp(_) => "cmp",
+            Bit(_) => "bit",
+            Jmp(_) => "jmp",
+            Bcc(_) => "bcc",
+            Bcs(_) => "bcs",
+            Bne(_) => "bne",
+            Beq(_) => "beq",
+            Bpl(_) => "bpl",
+            Bmi(_) => "bmi",
+            Clc    => "clc",
+            Sec    => "sec",
+            Cal(_) => "cal",
+            Ret    => "ret",
+            Nop    => "nop",
+            Hcf    => "hcf",
+        }
+    }
+}
+
+/// Branch offsets are printed as the raw offset word, `#xx`; jump targets as 
+/// a literal address `llhh`.
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Instruction::*;
+        let name = self.name();
+        match self {
+            Mov(x) | Xch(x) | Add(x) | Sub(x) | Mul(x) | Muh(x) | Mus(x) | Div(x) | Dis(x)
+            | Mod(x) | Mos(x) | And(x) | Or(x) | Xor(x) | Cmp(x) | Bit(x) => write!(f, "{} {}", name, x),
+            Psh(x) | Pop(x) | Not(x) | Lsl(x) | Lsr(x) | Asr(x) | Inc(x) | Dec(x) => {
+                write!(f, "{} {}", name, x)
+            }
+            Jmp(x) | Cal(x) => write!(f, "{} {}", name, Hex(*x)),
+            Bcc(x) | Bcs(x) | Bne(x) | Beq(x) | Bpl(x) | Bmi(x) => {
+                write!(f, "{} #{:02x}", name, x.as_uword().value())
+            }
+            Clc | Sec | Ret | Nop | Hcf => write!(f, "{}", name),
+        }
+    }
+}
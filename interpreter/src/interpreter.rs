@@ -1,107 +1,357 @@
 use super::prelude::*;
+use crate::paradox::{Conflict, ParadoxReport};
+use crate::power;
+use std::borrow::Cow;
+use crate::timing::{self, TimeUnit};
 
 //
 
-fn operand_to_ref_inner<'a>(state: &'a Machine, op: &'a Op) -> &'a uWord {
+pub fn operand_read_inner(state: &Machine, op: &Op) -> uWord {
     use Op::*;
     use Register::*;
 
     match op {
-        Reg(A)  => &state.cpu.a,
-        Reg(BH) => &state.cpu.bh,
-        Reg(BL) => &state.cpu.bl,
-        Reg(CH) => &state.cpu.ch,
-        Reg(CL) => &state.cpu.cl,
-        Reg(X)  => &state.cpu.x,
-        Imm(op) => &op,
-        Abs(op) => &state.ram[*op],
-        Abx(op) => {
-            let address = uLong::from(*op + state.cpu.x.value());
-            &state.ram[address]
-        }
-        Ind(op) => {
-            let lo = state.ram[*op];
-            let hi = state.ram[usize::from(*op) + 1];
-            let address = uLong::from_hi_lo(hi, lo);
-            &state.ram[address]
-        }
+        Reg(A)  => state.cpu.a,
+        Reg(F)  => state.cpu.flags.word(),
+        Reg(BH) => state.cpu.bh,
+        Reg(BL) => state.cpu.bl,
+        Reg(CH) => state.cpu.ch,
+        Reg(CL) => state.cpu.cl,
+        Reg(X)  => state.cpu.x,
+        Reg(SPH) => state.cpu.sp.hi(),
+        Reg(SPL) => state.cpu.sp.lo(),
+        Imm(op) => *op,
+        _ => state.ram[operand_address(state, op)],
     }
 }
 
-fn operand_to_mut_ref_inner<'a>(state: &'a mut Machine, op: &'a Op) -> &'a mut uWord {
+/// Fails if the memory map forbids the write
+fn operand_write_inner(state: &mut Machine, op: &Op, value: uWord) -> Result<(), Fault> {
     use Op::*;
     use Register::*;
 
     match op {
-        Reg(A)  => &mut state.cpu.a,
-        Reg(BH) => &mut state.cpu.bh,
-        Reg(BL) => &mut state.cpu.bl,
-        Reg(CH) => &mut state.cpu.ch,
-        Reg(CL) => &mut state.cpu.cl,
-        Reg(X)  => &mut state.cpu.x,
-        Imm(_)  => panic!("Illegal: attempted to write to immediate."),
-        Abs(op) => &mut state.ram[*op],
-        Abx(op) => {
-            let address = uLong::from(*op + state.cpu.x.value());
-            &mut state.ram[address]
-        }
-        Ind(op) => {
+        Reg(A)  => state.cpu.a = value,
+        Reg(BH) => state.cpu.bh = value,
+        Reg(BL) => state.cpu.bl = value,
+        Reg(CH) => state.cpu.ch = value,
+        Reg(CL) => state.cpu.cl = value,
+        Reg(X)  => state.cpu.x = value,
+        Reg(SPH) => state.cpu.sp = Address::from_hi_lo(value, state.cpu.sp.lo()),
+        Reg(SPL) => state.cpu.sp = Address::from_hi_lo(state.cpu.sp.hi(), value),
+        Reg(F) | Imm(_) => unreachable!("Writes to read-only operands fault in operand_set"),
+        _ => {
+            let address = operand_address(state, op);
+            return state.store(address, value)
+        }
+    }
+    Ok(())
+}
+
+/// Address of a memory operand
+fn operand_address(state: &Machine, op: &Op) -> Address {
+    match op {
+        Op::Abs(op) => *op,
+        Op::Abx(op) => uLong::from(*op + state.cpu.x.value()),
+        Op::Ind(op) => {
             let lo = state.ram[*op];
             let hi = state.ram[usize::from(*op) + 1];
-            let address = uLong::from_hi_lo(hi, lo);
-            &mut state.ram[address]
+            uLong::from_hi_lo(hi, lo)
         }
+        Op::Inr(Pair::B) => Address::from_hi_lo(state.cpu.bh, state.cpu.bl),
+        Op::Inr(Pair::C) => Address::from_hi_lo(state.cpu.ch, state.cpu.cl),
+        Op::Reg(_) | Op::Imm(_) => unreachable!("Not a memory operand"),
     }
 }
 
+/// Notes a read (`value` is None) or write of `op` in the state at time `t`,
+/// if it is to memory-mapped IO
+fn log_bus(universe: &mut Universe, t: usize, op: &Op, value: Option<uWord>) {
+    if matches!(op, Op::Reg(_) | Op::Imm(_)) { return };
+    let address = operand_address(&universe[t], op);
+    if !IO_WINDOW.contains(&usize::from(address)) { return };
+    universe.bus.push(match value {
+        None => BusAccess::Read { t, address },
+        Some(value) => BusAccess::Write { t, address, value },
+    })
+}
+
 //
 
-fn operand_get<'a>(universe: &'a mut Universe, operand: &'a Operand) -> &'a uWord {
+/// Checks that a jump to `t2` stays inside the timeline that is kept in memory
+fn check_window(universe: &Universe, operand: &Operand, t2: usize) -> Result<(), EmuError> {
+    if universe.timeline.in_interval(t2) {
+        Ok(())
+    } else {
+        Err(EmuError::OutOfWindow { t: universe.t, offset: operand.time.value() as isize })
+    }
+}
+
+/// Checks that the time jump of `operand` is within the configured limit
+fn check_jump(universe: &Universe, operand: &Operand) -> Result<(), EmuError> {
+    let offset = operand.time.value() as isize;
+    let max = universe.config.max_jump;
+    if offset.unsigned_abs() <= max {
+        Ok(())
+    } else {
+        Err(EmuError::JumpOutOfRange { t: universe.t, offset, max })
+    }
+}
+
+/// Feeds the jump of `operand` to the power model. Returns the operand as it
+/// happens, or faults if the supply browned out.
+fn power_jump<'a>(universe: &mut Universe, operand: &'a Operand) -> Result<Cow<'a, Operand>, EmuError> {
+    universe.power.jump(operand).map_err(|x| fault(universe, x))
+}
+
+/// Chooses the values that reads from the future start from, while looking
+/// for a consistent timeline
+pub trait ConsistencyStrategy {
+    /// Value to assume for a read of `op` at time `t`, in the future. 
+    /// `previous` is the value there in the last iteration, if that time was
+    /// already computed.
+    fn guess(&mut self, t: usize, op: &Op, previous: Option<uWord>) -> uWord;
+
+    /// The read of `op` at `t` guessed `guessed`, but found `observed`
+    fn conflict(&mut self, _t: usize, _op: &Op, _guessed: uWord, _observed: uWord) {}
+
+    /// Reads before time `t` are settled and will not be asked about again
+    fn forget(&mut self, _t: usize) {}
+}
+
+/// Start from 0, then substitute back what was found
+pub struct Naive;
+
+impl ConsistencyStrategy for Naive {
+    fn guess(&mut self, _t: usize, _op: &Op, previous: Option<uWord>) -> uWord {
+        previous.unwrap_or(uWord::ZERO)
+    }
+}
+
+/// Start from a chosen value, then substitute back what was found
+pub struct Seeded(pub uWord);
+
+impl ConsistencyStrategy for Seeded {
+    fn guess(&mut self, _t: usize, _op: &Op, previous: Option<uWord>) -> uWord {
+        previous.unwrap_or(self.0)
+    }
+}
+
+/// Try every value in turn for each read, moving on to the next one whenever
+/// a read turns out inconsistent. Finds a consistent value, if one exists, for
+/// a single read in at most 64 iterations.
+#[derive(Default)]
+pub struct Exhaustive {
+    candidates: Vec<(usize, Op, u8)>,
+}
+
+impl ConsistencyStrategy for Exhaustive {
+    fn guess(&mut self, t: usize, op: &Op, _previous: Option<uWord>) -> uWord {
+        let candidate = self.candidates.iter()
+            .find(|x| x.0 == t && &x.1 == op)
+            .map_or(0, |x| x.2);
+        uWord::try_from(candidate).unwrap()
+    }
+
+    fn conflict(&mut self, t: usize, op: &Op, _guessed: uWord, _observed: uWord) {
+        match self.candidates.iter_mut().find(|x| x.0 == t && &x.1 == op) {
+            Some(x) => x.2 = (x.2 + 1) % 64,
+            None => self.candidates.push((t, op.clone(), 1)),
+        }
+    }
+
+    fn forget(&mut self, t: usize) {
+        self.candidates.retain(|x| x.0 >= t)
+    }
+}
+
+/// Substitute back what was found, like [`Naive`], but restart a read from a
+/// random value after every `restart_after` inconsistencies, to escape cycles
+pub struct Restarts {
+    pub restart_after: usize,
+    rng: u64,
+    /// Inconsistencies so far of each read, and the value to restart it from
+    conflicts: Vec<(usize, Op, usize, Option<uWord>)>,
+}
+
+impl Restarts {
+    pub fn new(restart_after: usize, seed: u64) -> Self {
+        // Xorshift gets stuck on 0
+        Restarts { restart_after, rng: seed | 1, conflicts: vec![] }
+    }
+
+    fn random(&mut self) -> uWord {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        uWord::try_from((self.rng >> 32) as u8 & 0x3f).unwrap()
+    }
+}
+
+impl ConsistencyStrategy for Restarts {
+    fn guess(&mut self, t: usize, op: &Op, previous: Option<uWord>) -> uWord {
+        let restart = self.conflicts.iter_mut()
+            .find(|x| x.0 == t && &x.1 == op)
+            .and_then(|x| x.3.take());
+        restart.or(previous).unwrap_or(uWord::ZERO)
+    }
+
+    fn conflict(&mut self, t: usize, op: &Op, _guessed: uWord, _observed: uWord) {
+        let value = self.random();
+        let i = match self.conflicts.iter().position(|x| x.0 == t && &x.1 == op) {
+            Some(i) => i,
+            None => { self.conflicts.push((t, op.clone(), 0, None)); self.conflicts.len() - 1 }
+        };
+        let x = &mut self.conflicts[i];
+        x.2 += 1;
+        if x.2.is_multiple_of(self.restart_after) { x.3 = Some(value) };
+    }
+
+    fn forget(&mut self, t: usize) {
+        self.conflicts.retain(|x| x.0 >= t)
+    }
+}
+
+/// Parses a strategy, as given on the command line: `naive`, `seed:NN` (hex),
+/// `exhaustive`, or `random[:N]` (restarting after every N inconsistencies,
+/// default 8)
+pub fn parse_strategy(s: &str) -> Option<Box<dyn ConsistencyStrategy>> {
+    let (name, arg) = match s.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (s, None),
+    };
+    match (name, arg) {
+        ("naive", None) => Some(Box::new(Naive)),
+        ("seed", Some(x)) => {
+            let seed = u8::from_str_radix(x, 16).ok()?;
+            Some(Box::new(Seeded(uWord::try_from(seed).ok()?)))
+        }
+        ("exhaustive", None) => Some(Box::new(Exhaustive::default())),
+        ("random", arg) => {
+            let restart_after = match arg {
+                Some(x) => x.parse().ok().filter(|&x| x > 0)?,
+                None => 8,
+            };
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH).ok()?
+                .as_nanos() as u64;
+            Some(Box::new(Restarts::new(restart_after, seed)))
+        }
+        _ => None,
+    }
+}
+
+fn operand_get(universe: &mut Universe, operand: &Operand) -> Result<uWord, EmuError> {
+    check_jump(universe, operand)?;
+    let operand = &power_jump(universe, operand)?;
     let t1 = universe.t - 1;  // -1: because we read from the state before execution and write to state after execution
     let t2 = t1 + operand.time;
     // Trivial reads (present or past)
-    if operand.time.value() <= 0 {
-        operand_to_ref_inner(&universe[t2], &operand.op)  //offbyone
+    let value = if operand.time.value() <= 0 {
+        check_window(universe, operand, t2)?;
+        log_bus(universe, t2, &operand.op, None);
+        operand_read_inner(&universe[t2], &operand.op)  //offbyone
     }
     // Reads from the future
     else {
         // Does that moment in the future not even exist? Then we need to run until it does and then check consistency
-        if (t2) >= (universe.timeline.tf()) || universe.timeline.tf() == universe.t + 1 {
-            universe.pending_reads.push((t2, t1, operand.op.clone(), uWord::lit(0)));  // Bootstrap with 0
-            &uWord::ZERO
+        let previous = if (t2) >= (universe.timeline.tf()) || universe.timeline.tf() == universe.t + 1 {
+            None
         }
         // If it already does
         else {
-            let value = operand_to_ref_inner(&universe.timeline[t2], &operand.op);
-            universe.pending_reads.push((t2, t1, operand.op.clone(), *value));
-            value
-        }
-    }
+            Some(operand_read_inner(&universe.timeline[t2], &operand.op))
+        };
+        // Bootstrap with a guess (or, while iterating, with what was there)
+        let value = universe.strategy.guess(t2, &operand.op, previous);
+        universe.pending_reads.push(t2, universe.t, (operand.op.clone(), value));
+        value
+    };
+    Ok(value)
 }
 
-fn operand_set<'a>(universe: &'a mut Universe, operand: &'a Operand, value: uWord) {
+fn operand_set(universe: &mut Universe, operand: &Operand, value: uWord) -> Result<(), EmuError> {
+    check_jump(universe, operand)?;
+    let operand = &power_jump(universe, operand)?;
     let t1 = universe.t;
     let t2 = t1 + operand.time;
+    // Writing to the flags or an immediate faults, at any time
+    if operand.op.is_read_only() {
+        return Err(fault(universe, Fault::ReadOnly))
+    }
     // Trivial write (present)
-    if operand.time.value() == 0 {
-        *operand_to_mut_ref_inner(universe.now_mut(), &operand.op) = value;
+    else if operand.time.value() == 0 {
+        if let Err(x) = operand_write_inner(universe.now_mut(), &operand.op, value) {
+            return Err(fault(universe, x))
+        }
+        log_bus(universe, t1, &operand.op, Some(value));
     }
     // Trivial write (future, add to pending writes)
     else if operand.time.value() > 0 {
-        universe.pending_writes.push((t2, operand.op.clone(), value));
+        universe.pending_writes.push(t2, t1, (operand.op.clone(), value));
     }
     // Non-trivial write (past)
     else {
-        // Is this inconsistent with what was already recorded?
-        if *operand_to_ref_inner(&universe[t2], &operand.op) == value {
-            /*universe.pending_writes.push((t2, operand.op.clone(), value));*/  // Put in pending writes anyway, in case we need to rewind further back
-            ()  // ok
-        } else {
-            dprintln!("Inconsistent! Writing value {} to where was {}", value.value(), operand_to_ref_inner(&universe[t2], &operand.op).value());
-            *operand_to_mut_ref_inner(&mut universe[t2], &operand.op) = value;
-            universe.mode.add_inconsistent(t2 /*- 1*/, t1+4/*+1*/);
+        check_window(universe, operand, t2)?;
+        if let Err(x) = write_past(universe, t2, &operand.op, value) {
+            return Err(fault(universe, x))
         }
+        log_bus(universe, t2, &operand.op, Some(value));
+    }
+    Ok(())
+}
+
+/// Writes `value` to `op` in the state at `t2`, before the present. If that
+/// changes what was recorded, the timeline is inconsistent from there.
+fn write_past(universe: &mut Universe, t2: usize, op: &Op, value: uWord) -> Result<(), Fault> {
+    let t1 = universe.t;
+    // Is this inconsistent with what was already recorded?
+    let recorded = operand_read_inner(&universe[t2], op);
+    if recorded == value {
+        /*universe.pending_writes.push((t2, operand.op.clone(), value));*/  // Put in pending writes anyway, in case we need to rewind further back
+        ()  // ok
+    } else {
+        dprintln!("Inconsistent! Writing value {} to where was {}", value.value(), recorded.value());
+        operand_write_inner(&mut universe[t2], op, value)?;
+        universe.mode.add_inconsistent(t2 /*- 1*/, t1 + universe.config.rewind_padding);
+        universe.paradox.add(Conflict::Write { t: t2, op: op.clone(), recorded, written: value });
+    }
+    Ok(())
+}
+
+/// Writes `value` to `address` in the state at `t`, for an IO module that was
+/// told of an access to that state: at once if it is the present, else as a
+/// write to the past. It is not itself an access on the bus.
+pub fn write_io(universe: &mut Universe, t: usize, address: Address, value: uWord) -> Result<(), Fault> {
+    let op = Op::Abs(address);
+    match t == universe.t {
+        true => operand_write_inner(universe.now_mut(), &op, value),
+        false => write_past(universe, t, &op, value),
+    }
+}
+
+/// Faults if writing `operand` would, without writing it (writes to the
+/// future are only checked when they land)
+fn check_set(universe: &mut Universe, operand: &Operand) -> Result<(), EmuError> {
+    check_jump(universe, operand)?;
+    if operand.op.is_read_only() {
+        return Err(fault(universe, Fault::ReadOnly))
     }
+    if operand.time.value() > 0 || matches!(operand.op, Op::Reg(_)) { return Ok(()) };
+    let t2 = universe.t + operand.time;
+    check_window(universe, operand, t2)?;
+    let state = &universe[t2];
+    match state.check_store(operand_address(state, &operand.op)) {
+        Ok(()) => Ok(()),
+        Err(x) => Err(fault(universe, x)),
+    }
+}
+
+/// Faults the CPU, which ends the instruction: returns the error to pass up
+/// from `execute`
+fn fault(universe: &mut Universe, fault: Fault) -> EmuError {
+    universe.now_mut().cpu.fault = Some(fault);
+    EmuError::Fault(fault)
 }
 
 //
@@ -116,16 +366,14 @@ fn set_flag_n(state: &mut Machine, value: &uWord) {
         .write(Flag::N, value.sign_bit())
 }
 
-fn set_flag_v(state: &mut Machine, value: &uWord) {
-    // Hacker's delight: signed overflow occurs if carry = sign bit
-    let carry = get_flag_c(state);
+fn set_flag_v(state: &mut Machine, overflow: bool) {
     state.cpu.flags
-        .write(Flag::V, carry == value.sign_bit())
+        .write(Flag::V, overflow)
 }
 
-fn set_flag_nvz(state: &mut Machine, value: &uWord) {
+fn set_flag_nvz(state: &mut Machine, value: &uWord, overflow: bool) {
     set_flag_n(state, value);
-    set_flag_v(state, value);
+    set_flag_v(state, overflow);
     set_flag_z(state, value);
 }
 
@@ -147,182 +395,240 @@ fn normalise(x: u8) -> (uWord, bool) {
     (uWord::lit(result), carry)
 }
 
-fn execute(state: &mut Universe, instruction: &Instruction) {
+// Aux function: whether a + b (+ carry) = result overflows as a signed sum. 
+// Hacker's delight: iff a and b have the same sign, and result has another
+fn overflows(a: u8, b: u8, result: &uWord) -> bool {
+    let r = result.value();
+    (a ^ r) & (b ^ r) & (1 << (WORD_SIZE-1)) != 0
+}
+
+fn execute(state: &mut Universe, instruction: &Instruction) -> Result<(), EmuError> {
     let get = operand_get;
     let set = operand_set;
 
     match instruction {
         // Memory
         Instruction::Mov(Operands{ src, dst }) => {
-            let word = *get(state, &src);
-            set(state, &dst, word);
-            set_flag_nvz(state.now_mut(), &word);
+            let word = get(state, src)?;
+            set(state, dst, word)?;
+            set_flag_nvz(state.now_mut(), &word, false);
+        }
+        Instruction::Xch(Operands{ src, dst }) => {
+            // Both reads happen before either write, and neither write
+            // happens if the other would fault
+            let a = get(state, src)?;
+            let b = get(state, dst)?;
+            check_set(state, src)?;
+            check_set(state, dst)?;
+            set(state, src, b)?;
+            set(state, dst, a)?;
         }
         Instruction::Psh(x) => {
-            let word = *get(state, &x);
-            state.now_mut().write_sp(word);
+            let word = get(state, x)?;
+            if let Err(fault) = state.now_mut().push(&[word]) {
+                state.now_mut().cpu.fault = Some(fault)
+            }
         }
         Instruction::Pop(x) => {
-            let word = state.now_mut().read_sp();
-            set(state, &x, word);
-            set_flag_nvz(state.now_mut(), &word);
+            // Nothing is popped if the write would fault
+            check_set(state, x)?;
+            match state.now_mut().pop() {
+                Ok([word]) => {
+                    set(state, x, word)?;
+                    set_flag_nvz(state.now_mut(), &word, false);
+                }
+                Err(fault) => state.now_mut().cpu.fault = Some(fault),
+            }
         }
 
         // Arithmetic
         Instruction::Add(Operands { src, dst }) => {
-            let a = get(state, &src).value();
-            let b = get(state, &dst).value();
+            let a = get(state, src)?.value();
+            let b = get(state, dst)?.value();
             let carry = u8::from(get_flag_c(state.now()));
             let (result, carry) = normalise(a + b + carry);
-            set(state, &dst, result);
+            set(state, dst, result)?;
             set_flag_c(state.now_mut(), carry);
-            set_flag_nvz(state.now_mut(), &result);
+            set_flag_nvz(state.now_mut(), &result, overflows(a, b, &result));
         }
         Instruction::Sub(Operands { src, dst }) => {
-            let a = get(state, &src).value();
-            let b = get(state, &dst).value();
+            let a = get(state, src)?.value();
+            let b = get(state, dst)?.value();
             let borrow = u8::from(!get_flag_c(state.now()));
             let (result, carry) = normalise((1<<WORD_SIZE) - a + b - borrow);
-            set(state, &dst, result);
+            set(state, dst, result)?;
             set_flag_c(state.now_mut(), carry);
-            set_flag_nvz(state.now_mut(), &result);
+            // b - a - borrow = b + !a + carry
+            let not_a = !a & uWord::MAX.value();
+            set_flag_nvz(state.now_mut(), &result, overflows(not_a, b, &result));
         }
 
         Instruction::Mul(Operands { src, dst }) => {
-            let a = get(state, &src).value() as u16;
-            let b = get(state, &dst).value() as u16;
+            let a = get(state, src)?.value() as u16;
+            let b = get(state, dst)?.value() as u16;
             let result = uLong::try_from(a * b).unwrap().lo();
-            set(state, &dst, result);
-            /*set_flag_c(state.now_mut(), carry);*/
-            set_flag_nvz(state.now_mut(), &result);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Muh(Operands { src, dst }) => {
-            let a = get(state, &src).value() as u16;
-            let b = get(state, &dst).value() as u16;
+            let a = get(state, src)?.value() as u16;
+            let b = get(state, dst)?.value() as u16;
             let raw = (a * b) >> WORD_SIZE;
-            let (result, carry) = normalise(raw as u8);
-            set(state, &dst, result);
-            set_flag_c(state.now_mut(), carry);
-            set_flag_nvz(state.now_mut(), &result);
+            let (result, _) = normalise(raw as u8);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Mus(Operands { src, dst }) => {
-            let a = get(state, &src).as_iword().value() as i16;
-            let b = get(state, &dst).as_iword().value() as i16;
+            let a = get(state, src)?.as_iword().value() as i16;
+            let b = get(state, dst)?.as_iword().value() as i16;
             let raw = (a * b) >> WORD_SIZE;
-            let (result, carry) = normalise(raw as u8);
-            set(state, &dst, result);
-            set_flag_c(state.now_mut(), carry);
-            set_flag_nvz(state.now_mut(), &result);
+            let (result, _) = normalise(raw as u8);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Div(Operands { src, dst }) => {
-            if get(state, &src).value() != 0 {
-                let a = get(state, &src).value();
-                let b = get(state, &dst).value();
-                let (result, _) = normalise(a / b);
-                set(state, &dst, result);
+            let a = get(state, src)?.value();
+            let b = get(state, dst)?.value();
+            let dividend = b + (u8::from(get_flag_c(state.now())) << WORD_SIZE);
+            // Division by zero leaves dst untouched and sets C
+            if a != 0 {
+                let (result, carry) = normalise(dividend / a);
+                set(state, dst, result)?;
+                set_flag_z(state.now_mut(), &result);
+                set_flag_c(state.now_mut(), carry);
+            } else {
+                set_flag_c(state.now_mut(), true);
+            }
+        }
+        Instruction::Dis(Operands { src, dst }) => {
+            let a = get(state, src)?.as_iword().value();
+            let b = get(state, dst)?.as_iword().value();
+            // Division by zero leaves dst untouched and sets C
+            if a != 0 {
+                let (result, _) = normalise((b / a) as u8);
+                set(state, dst, result)?;
+                set_flag_n(state.now_mut(), &result);
                 set_flag_z(state.now_mut(), &result);
             }
+            set_flag_c(state.now_mut(), a == 0);
         }
         Instruction::Mod(Operands { src, dst }) => {
-            if get(state, &src).value() != 0 {
-                let a = get(state, &src).value();
-                let b = get(state, &dst).value();
-                let (result, _) = normalise(a % b);
-                set(state, &dst, result);
+            let a = get(state, src)?.value();
+            let b = get(state, dst)?.value();
+            let dividend = b + (u8::from(get_flag_c(state.now())) << WORD_SIZE);
+            // Division by zero leaves dst untouched and sets C
+            if a != 0 {
+                let (result, _) = normalise(dividend % a);
+                set(state, dst, result)?;
                 set_flag_z(state.now_mut(), &result);
             }
+            set_flag_c(state.now_mut(), a == 0);
+        }
+        Instruction::Mos(Operands { src, dst }) => {
+            let a = get(state, src)?.as_iword().value();
+            let b = get(state, dst)?.as_iword().value();
+            // Division by zero leaves dst untouched and sets C
+            if a != 0 {
+                let (result, _) = normalise((b % a) as u8);
+                set(state, dst, result)?;
+                set_flag_n(state.now_mut(), &result);
+                set_flag_z(state.now_mut(), &result);
+            }
+            set_flag_c(state.now_mut(), a == 0);
         }
 
         // Logic
         Instruction::And(Operands { src, dst }) => {
-            let raw = get(state, &src).value() & get(state, &dst).value();
+            let raw = get(state, src)?.value() & get(state, dst)?.value();
             let (result, _) = normalise(raw);
-            set(state, &dst, result);
-            set_flag_nvz(state.now_mut(), &result);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Or(Operands { src, dst }) => {
-            let raw = get(state, &src).value() | get(state, &dst).value();
+            let raw = get(state, src)?.value() | get(state, dst)?.value();
             let (result, _) = normalise(raw);
-            set(state, &dst, result);
-            set_flag_nvz(state.now_mut(), &result);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Xor(Operands { src, dst }) => {
-            let raw = get(state, &src).value() ^ get(state, &dst).value();
+            let raw = get(state, src)?.value() ^ get(state, dst)?.value();
             let (result, _) = normalise(raw);
-            set(state, &dst, result);
-            set_flag_nvz(state.now_mut(), &result);
+            set(state, dst, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Not(x) => {
-            let raw = !get(state, &x).value();
+            let raw = !get(state, x)?.value();
             let (result, _) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            set(state, x, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
         }
         Instruction::Lsl(x) => {
-            let raw = get(state, &x).value() << 1;
-            let (result, carry) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            let a = get(state, x)?.value();
+            let (result, carry) = normalise(a << 1);
+            set(state, x, result)?;
+            // Overflows if the sign bit changes
+            set_flag_nvz(state.now_mut(), &result, overflows(a, a, &result));
             set_flag_c(state.now_mut(), carry);
         }
         Instruction::Lsr(x) => {
-            let a: u8 = get(state, &x).value();
-            let carry = u8::from(get_flag_c(state.now())) << WORD_SIZE;
-            let raw = (a >> 1) + carry;
-            let (result, _) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            let a = get(state, x)?.value();
+            let carry = u8::from(get_flag_c(state.now())) << (WORD_SIZE-1);
+            let (result, _) = normalise((a >> 1) | carry);
+            set(state, x, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
+            set_flag_c(state.now_mut(), a & 1 != 0);
         }
         Instruction::Asr(x) => {
-            let a: i8 = get(state, &x).as_iword().value();
-            let carry = u8::from(get_flag_c(state.now())) << WORD_SIZE;
-            let raw = (a >> 1) as u8 + carry;
-            let (result, _) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            let a = get(state, x)?.as_iword().value();
+            let result = iWord::try_from(a >> 1).unwrap().as_uword();
+            set(state, x, result)?;
+            set_flag_nvz(state.now_mut(), &result, false);
+            set_flag_c(state.now_mut(), a & 1 != 0);
         }
         Instruction::Inc(x) => {
-            let raw: u8 = get(state, &x).value() + 1;
-            let (result, _) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            let a = get(state, x)?.value();
+            let (result, _) = normalise(a + 1);
+            set(state, x, result)?;
+            set_flag_nvz(state.now_mut(), &result, overflows(a, 0, &result));
         }
         Instruction::Dec(x) => {
-            let raw: u8 = get(state, &x).value() - 1;
-            let (result, _) = normalise(raw);
-            set(state, &x, result);
-            set_flag_nvz(state.now_mut(), &result);
+            let a = get(state, x)?.value();
+            // a - 1 = a + 0x3f
+            let (result, _) = normalise(a + uWord::MAX.value());
+            set(state, x, result)?;
+            set_flag_nvz(state.now_mut(), &result, overflows(a, uWord::MAX.value(), &result));
         }
 
         // Comparisons, CMP = SUB, BIT = AND, mas deitar fora os argumentos
         Instruction::Cmp(Operands { src, dst }) => {
-            let a = get(state, &src).value();
-            let b = get(state, &dst).value();
+            let a = get(state, src)?.value();
+            let b = get(state, dst)?.value();
             let borrow = u8::from(!get_flag_c(state.now()));
-            let (result, carry) = normalise(a + b - borrow);
-            /*set(state, &dst, result);*/
+            let (result, carry) = normalise((1<<WORD_SIZE) - a + b - borrow);
+            /*set(state, dst, result)?;*/
             set_flag_c(state.now_mut(), carry);
-            set_flag_nvz(state.now_mut(), &result);
+            let not_a = !a & uWord::MAX.value();
+            set_flag_nvz(state.now_mut(), &result, overflows(not_a, b, &result));
         }
         Instruction::Bit(Operands { src, dst }) => {
-            let raw = get(state, &src).value() & get(state, &dst).value();
+            let raw = get(state, src)?.value() & get(state, dst)?.value();
             let (result, _) = normalise(raw);
-            /*set(state, &dst, result);*/
-            set_flag_nvz(state.now_mut(), &result);
+            /*set(state, dst, result)?;*/
+            set_flag_nvz(state.now_mut(), &result, false);
         }
 
         // Jumping
         Instruction::Jmp(addr) => state.now_mut().cpu.pc = *addr,
         Instruction::Cal(addr) => {
             let current_addr = state.now().cpu.pc;
-            state.now_mut().write_sp(current_addr.lo());
-            state.now_mut().write_sp(current_addr.hi());
-            state.now_mut().cpu.pc = *addr
+            match state.now_mut().push(&[current_addr.lo(), current_addr.hi()]) {
+                Ok(()) => state.now_mut().cpu.pc = *addr,
+                Err(fault) => state.now_mut().cpu.fault = Some(fault),
+            }
         }
-        Instruction::Ret => {
-            let hi = state.now_mut().read_sp();
-            let lo = state.now_mut().read_sp();
-            state.now_mut().cpu.pc = Address::from_hi_lo(hi, lo)
+        Instruction::Ret => match state.now_mut().pop() {
+            Ok([hi, lo]) => state.now_mut().cpu.pc = Address::from_hi_lo(hi, lo),
+            Err(fault) => state.now_mut().cpu.fault = Some(fault),
         }
 
         // Branching
@@ -361,70 +667,183 @@ fn execute(state: &mut Universe, instruction: &Instruction) {
         Instruction::Clc => state.now_mut().cpu.flags.write(Flag::C, false),
         Instruction::Sec => state.now_mut().cpu.flags.write(Flag::C, true),
         Instruction::Nop => (),
-        Instruction::Hcf => state.now_mut().cpu.pc = state.now().cpu.pc + (-1),  // Ugly hack
+        Instruction::Hcf => state.now_mut().cpu.halted = true,
     }
+    Ok(())
+}
+
+/// A step that went through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// Executed this instruction
+    Running(Instruction),
+    /// Executed HCF
+    Halted,
+    /// Still executing the last instruction (when a slot is a clock cycle)
+    Busy,
 }
 
+/// A step that could not go through
+#[derive(Debug)]
+pub enum EmuError {
+    /// No consistent timeline was found within the iteration limit, while 
+    /// resolving times `ti` to `tf`. `report` has every rewind taken.
+    Paradox { ti: usize, tf: usize, report: ParadoxReport },
+    /// A time jump of `offset` at time `t` lands outside the states kept in 
+    /// memory
+    OutOfWindow { t: usize, offset: isize },
+    /// A time jump of `offset` at time `t` is farther than the configured
+    /// `max`
+    JumpOutOfRange { t: usize, offset: isize, max: usize },
+    /// The CPU faulted (and is halted)
+    Fault(Fault),
+    /// An IO module failed
+    Io(Box<dyn std::error::Error>),
+}
+
+impl std::fmt::Display for EmuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmuError::Paradox { ti, tf, report } => match report.last() {
+                Some(conflict) => 
+                    write!(f, "paradox: no consistent timeline for t={}..{} ({})", ti, tf, conflict),
+                None => 
+                    write!(f, "paradox: no consistent timeline for t={}..{}", ti, tf),
+            },
+            EmuError::OutOfWindow { t, offset } => 
+                write!(f, "time jump of {} at t={} leaves the timeline window", offset, t),
+            EmuError::JumpOutOfRange { t, offset, max } => 
+                write!(f, "time jump of {} at t={} is farther than the limit of {}", offset, t, max),
+            EmuError::Fault(x) => write!(f, "{}", x),
+            EmuError::Io(x) => write!(f, "IO module failed: {}", x),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
+
 /// Performs one micro step on `universe`
-pub fn step_micro(universe: &mut Universe, modules: &mut ModuleCollection) {
+pub fn step_micro(universe: &mut Universe, modules: &mut ModuleCollection) -> Result<StepOutcome, EmuError> {
     dprintln!("μStep: t={} mode={:?}", universe.t, universe.mode);
+    universe.stats.micro_steps += 1;
 
     // Pending reads, são aqui que se checam
-    let pending_reads_ = 
-        universe.pending_reads.clone().into_iter().filter(|(t, ti, op, value)| {
-            if *t == universe.t {
-                let state = universe.now();
-                if *operand_to_ref_inner(state, &op) == *value {
-                    false//true
-                } else {
-                    universe.mode.add_inconsistent(*ti, *t);
-                    false
-                }
-            } else {
-                true
-            }
-        });
-    universe.pending_reads = pending_reads_.collect::<Vec<_>>();  // ::<_>>()#@__zyx$$&ph'nglui mglw'nafh Cthulhu R'lyeh wgah'nagl fhtagn
+    let t = universe.t;
+    let mut conflicts = vec![];
+    let mut reads = vec![];
+    for (issued, (op, guessed)) in universe.pending_reads.at(t) {
+        reads.push(op.clone());
+        let observed = operand_read_inner(&universe.timeline[t], op);
+        if observed != *guessed {
+            // Re-run from the read (in the state before it) up to and 
+            // including this state
+            universe.mode.add_inconsistent(issued - 1, t + 1);
+            conflicts.push(Conflict::Read { t, op: op.clone(), guessed: *guessed, observed });
+        }
+    }
+    for conflict in conflicts {
+        if let Conflict::Read { t, op, guessed, observed } = &conflict {
+            universe.strategy.conflict(*t, op, *guessed, *observed)
+        }
+        universe.paradox.add(conflict)
+    };
+    for op in reads { log_bus(universe, universe.t, &op, None) };
 
     dprintln!(">read   t={} mode={:?}", universe.t, universe.mode);
 
-    universe.push_new_state();
+    universe.push_new_state()?;
 
     dprintln!(">push  t={} mode={:?}", universe.t, universe.mode);
 
-    modules.run(universe);
-    let instruction = Instruction::decode(universe.now_mut());
-    execute(universe, &instruction);
+    modules.run(universe).map_err(EmuError::Io)?;
+    // A halted or faulted CPU does nothing, but the rest of the universe goes on
+    let pc = universe.now().cpu.pc;
+    let cpu = &universe.now().cpu;
+    let busy = cpu.busy > 0;
+    let instruction = match cpu.halted || cpu.fault.is_some() || busy {
+        true => None,
+        false => match Instruction::decode(universe.now_mut()) {
+            Ok(instruction) => Some(instruction),
+            Err(err) => {
+                universe.now_mut().cpu.fault = Some(Fault::Decode(err));
+                None
+            }
+        }
+    };
+    if let Some(instruction) = &instruction {
+        match execute(universe, instruction) {
+            // A fault ends the instruction, and is reported below
+            Ok(()) | Err(EmuError::Fault(_)) => (),
+            Err(err) => return Err(err),
+        }
+    };
+    // Leave the PC at the faulting instruction
+    if universe.now().cpu.fault.is_some() { universe.now_mut().cpu.pc = pc };
+
+    // Advance the clock: by the cost of the instruction, or, if a slot is a
+    // cycle, by one, keeping the CPU busy for the rest of the instruction
+    let cost = instruction.as_ref().map_or(1, timing::cycles);
+    let unit = universe.unit;
+    let m = universe.now_mut();
+    match unit {
+        TimeUnit::Instructions => m.cycles += cost as u64,
+        TimeUnit::Cycles => {
+            m.cycles += 1;
+            // A stopped CPU has nothing left to finish
+            m.cpu.busy = match m.cpu.halted || m.cpu.fault.is_some() {
+                true => 0,
+                false if busy => m.cpu.busy - 1,
+                false => cost - 1,
+            };
+        }
+    }
 
     dprintln!(">exec  t={} mode={:?}", universe.t, universe.mode);
 
     // Pending writes, são aqui que se fazem
-    //TODO mike pls fix
-    let asdf = 
-        universe.pending_writes.clone().into_iter().filter(|(t, op, value)| {
-            if *t == universe.t {
-                let state = universe.now_mut();
-                *operand_to_mut_ref_inner(state, &op) = *value;
-                false
-            } else {
-                true
-            }
-        });
-    universe.pending_writes = asdf.collect::<Vec<_>>();
+    let writes = universe.pending_writes.at(universe.t).to_vec();
+    for (_, (op, value)) in writes {
+        let state = universe.now_mut();
+        if let Err(fault) = operand_write_inner(state, &op, value) {
+            state.cpu.fault = Some(fault)
+        }
+        log_bus(universe, universe.t, &op, Some(value));
+    }
+
+    // Power drawn by this step's jumps
+    let step = universe.power.finish(cost as u64);
+    power::record(universe.now_mut(), &step);
+
+    // Let the modules see this step's accesses to IO
+    modules.dispatch(universe).map_err(EmuError::Io)?;
+
+    let cpu = &universe.now().cpu;
+    let outcome = match (instruction, cpu.fault) {
+        (_, Some(fault)) => Err(EmuError::Fault(fault)),
+        (_, None) if cpu.halted => Ok(StepOutcome::Halted),
+        (Some(instruction), None) => Ok(StepOutcome::Running(instruction)),
+        (None, None) if busy => Ok(StepOutcome::Busy),
+        (None, None) => unreachable!("Only a stopped CPU executes nothing"),
+    };
 
     match universe.mode {
         // Maybe inconsistent: if we reach the end of the window, it is consistent
-        Mode::Maybe (ti, tf) if universe.t == tf => {
+        Mode::Maybe (_, tf) if universe.t == tf => {
             universe.mode = Mode::Consistent;
             dprintln!(">mode  t={} mode={:?}", universe.t, universe.mode);
-            return
+            return outcome
         }
         // Definitely inconsistent: if we reach the end of the window, rewind to start as "maybe consistent"
         Mode::Inconsistent (ti, tf) if universe.t == tf => {
             universe.mode = Mode::Maybe(ti, tf);
             universe.t = ti;
+            universe.pending_reads.rewind(ti);
+            universe.pending_writes.rewind(ti);
+            universe.stats.rewinds += 1;
+            universe.power.rewind(tf - ti);
+            universe.paradox.rewind(ti, tf);
             dprintln!(">mode  t={} mode={:?}", universe.t, universe.mode);
-            return
+            return outcome
         }
         // Anything else: continue execution
         _ => ()
@@ -435,57 +854,592 @@ pub fn step_micro(universe: &mut Universe, modules: &mut ModuleCollection) {
     dprintln!(">writ   t={} mode={:?}", universe.t, universe.mode);
 
     if cfg!(debug_assertions) {
-        for i in &universe.pending_writes { dprintln!("pending w: {:?}", i) };
-        for i in &universe.pending_reads { dprintln!("pending r: {:?}", i) };
+        for i in universe.pending_writes.iter() { dprintln!("pending w: {:?}", i) };
+        for i in universe.pending_reads.iter() { dprintln!("pending r: {:?}", i) };
     }
 
-    println!(">finish");
-    println!("Instruction: {:?}", instruction);
-    println!("{}", universe.now());
+    dprintln!(">finish");
+    dprintln!("Outcome: {:?}", outcome);
+    dprintln!("{}", universe.now());
 
-    ()
+    outcome
 }
 
-/*pub enum Result {
-    /// Running, executed Instruction and updated to Machine
-    Running (Machine, Instruction), 
-    /// Cpu idle
-    Halted (Machine), 
-    /// Time inconsistency
-    Inconsistent, 
-}*/
-
 /// Performs one full step on universe: micro steps until a fixed state can be yielded and pushed onto universe. 
-/// Returns `false` iff inconsistency is reached
-pub fn step_one(universe: &mut Universe, modules: &mut ModuleCollection) -> bool {
-    // Prevent memory leak: every 2^12 iterations clean unreachable in pending_{reads,writes}
+/// A fault met while the timeline is still inconsistent is not final, and is
+/// only reported if it survives.
+pub fn step_one(universe: &mut Universe, modules: &mut ModuleCollection) -> Result<StepOutcome, EmuError> {
+    // Reads and writes for times that left the timeline can't be met again
+    let ti = universe.timeline.ti();
+    universe.pending_reads.forget(ti);
+    universe.pending_writes.forget(ti);
+    // Prevent memory leak: every 2^12 iterations clean what is unreachable
     if universe.t % (1<<12) == 0 {
-        let ti = universe.timeline.ti();
-        universe.pending_reads.retain(|x| x.0 >= ti);  
-        universe.pending_writes.retain(|x| x.0 >= ti);  
+        universe.strategy.forget(ti);
+        modules.log.forget(ti).map_err(|x| EmuError::Io(Box::new(x)))?;
     }
 
     // Do step_micro until we hit inconsistency
-    step_micro(universe, modules);
-    const INCONSISTENT_ITERATIONS_LIMIT: usize = 1000*10;
+    let mut outcome = step_micro(universe, modules);
     let mut inconsistent_iterations: usize = 0;
     while !universe.is_consistent() {
-        if inconsistent_iterations == INCONSISTENT_ITERATIONS_LIMIT { return false }
-        step_micro(universe, modules);
+        if let Err(err) = &outcome {
+            if !matches!(err, EmuError::Fault(_)) { return outcome }
+        }
+        if inconsistent_iterations == universe.config.iteration_limit {
+            let (ti, tf) = match universe.mode {
+                Mode::Maybe(ti, tf) | Mode::Inconsistent(ti, tf) => (ti, tf),
+                Mode::Consistent => unreachable!(),
+            };
+            let report = std::mem::take(&mut universe.paradox);
+            return Err(EmuError::Paradox { ti, tf, report })
+        }
+        outcome = step_micro(universe, modules);
         inconsistent_iterations += 1
     };
+    universe.paradox.clear();
 
-    true
+    outcome
 }
 
-/// Returns Some(Machine, Instruction) or None if time inconsistency was reached
-pub fn step(universe: &mut Universe, modules: &mut ModuleCollection) -> Option<(Machine, Instruction)> {
+/// Fills the timeline, and pops the state at its front, which is then fixed.
+/// Returns that state, with the instruction at its PC (the one it is about to
+/// execute). Once the machine has halted and nothing is left to resolve, the
+/// timeline is not filled further, and the final state is returned (from then
+/// on) as soon as a halted state is popped.
+pub fn step(universe: &mut Universe, modules: &mut ModuleCollection) -> Result<(Machine, StepOutcome), EmuError> {
     // Universe not full: continue filling
-    while !universe.timeline.is_full() {
-        if !step_one(universe, modules) { return None }
+    while !universe.timeline.is_full() && !universe.is_settled() {
+        match step_one(universe, modules) {
+            // Faults are reported when the faulted state is popped
+            Ok(_) | Err(EmuError::Fault(_)) => (),
+            Err(err) => return Err(err),
+        }
     } 
     // Universe full (and consistent): this means the state we pop from front is stable
-    let machine = universe.pop_state();
-    let instruction = Instruction::decode(&mut machine.clone());  // TODO overkill mas acho que não é bottleneck
-    Some((machine, instruction))
-}
\ No newline at end of file
+    let machine = match universe.timeline.ti() < universe.t {
+        true => universe.pop_state(),
+        false => universe.now().clone(),
+    };
+    if let Some(fault) = machine.cpu.fault { return Err(EmuError::Fault(fault)) };
+    if machine.cpu.busy > 0 { return Ok((machine, StepOutcome::Busy)) };
+    if machine.cpu.halted {
+        // Nothing runs anymore: skip to the final state
+        if universe.is_settled() {
+            while universe.timeline.ti() < universe.t { universe.pop_state(); }
+            return Ok((universe.now().clone(), StepOutcome::Halted))
+        }
+        return Ok((machine, StepOutcome::Halted))
+    };
+    let outcome = match Instruction::decode(&mut machine.clone()) {  // TODO overkill mas acho que não é bottleneck
+        Ok(instruction) => StepOutcome::Running(instruction),
+        Err(err) => return Err(EmuError::Fault(Fault::Decode(err))),
+    };
+    Ok((machine, outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_into;
+    use crate::universe::MAX_JUMP;
+    use crate::instruction::Timed;
+
+    /// Runs `steps` instructions of `source`, with the initial machine set up 
+    /// by `setup`, and returns the last state
+    fn run(source: &str, steps: usize, setup: impl FnOnce(&mut Machine)) -> Machine {
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        setup(universe.now_mut());
+        let mut modules = ModuleCollection::new(vec![]);
+        for _ in 0..steps {
+            match step_one(&mut universe, &mut modules) {
+                Ok(_) | Err(EmuError::Fault(_)) => (),
+                Err(err) => panic!("{}", err),
+            }
+        }
+        universe.now().clone()
+    }
+
+    /// Steps `source` until it fails
+    fn run_until_error(source: &str) -> EmuError {
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        loop {
+            match step(&mut universe, &mut modules) {
+                Err(err) => return err,
+                Ok((_, StepOutcome::Halted)) => panic!("Halted without error"),
+                Ok(_) => (),
+            }
+        }
+    }
+
+    #[test]
+    fn paradox() {
+        // Reads a word from the future, and writes back its negation
+        let source = "mov %0001@+3 a\nnot a\nmov a %0001\nhcf";
+        match run_until_error(source) {
+            EmuError::Paradox { report, .. } => {
+                let op = Op::Abs(Address::try_from(0x40).unwrap());
+                assert!(matches!(report.last(), Some(Conflict::Read { op: x, .. }) if *x == op));
+                // The guess alternates between a value and its negation
+                let cycles = report.cycles();
+                assert_eq!(cycles.len(), 1);
+                assert_eq!(cycles[0].op, op);
+                assert_eq!(cycles[0].values.len(), 2);
+                assert_eq!(cycles[0].values[0].value(), !cycles[0].values[1].value() & 0x3f);
+            }
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn out_of_window() {
+        match run_until_error("mov a@-100 bl") {
+            EmuError::OutOfWindow { t: 1, offset: -100 } => (),
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn fault() {
+        match run_until_error("mov a #01") {
+            EmuError::Fault(Fault::ReadOnly) => (),
+            err => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn decode_faults() {
+        let origin = Address::try_from(0x80).unwrap();
+        for (source, err) in [
+            ("dat 05", DecodeError::Opcode(uWord::lit(0x05))),
+            ("dat 01\ndat 00\ndat 3f", DecodeError::Register(uWord::lit(0x3f))),
+            ("dat 01\ndat 3f", DecodeError::Mode(uWord::lit(0x3f))),
+        ] {
+            // Stays faulted at the instruction, however long it runs
+            let m = run(source, 3, |_| ());
+            assert_eq!((m.cpu.fault, m.cpu.pc), (Some(Fault::Decode(err)), origin), "{}", source);
+            match run_until_error(source) {
+                EmuError::Fault(Fault::Decode(x)) => assert_eq!(x, err),
+                x => panic!("{}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn protection() {
+        // The program is the EPROM, from %0002; %0000 is reserved
+        let source = "mov #01 x\nmov #2a %0002,x\nmov #2a %0000\nhcf";
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        assert_eq!(universe.now().map.eprom_end, 0x80 + 15);
+        // By default, writes go through, and are logged
+        assert_eq!(universe.now().map.on_violation, OnViolation::Log);
+        let m = run(source, 3, |_| ());
+        assert_eq!((m.ram[0x81], m.ram[0x00]), (uWord::lit(0x2a), uWord::lit(0x2a)));
+        let m = run(source, 3, |m| m.map.on_violation = OnViolation::Fault);
+        assert_eq!(m.cpu.fault, Some(Fault::Protection(Address::try_from(0x81).unwrap())));
+        assert_eq!(m.ram[0x81], universe.now().ram[0x81]);
+
+        // Permissions are set per region
+        let source = "mov #2a %0001\nmov #2b %3f01";
+        let m = run(source, 2, |m| { m.map.on_violation = OnViolation::Fault; m.map.permissions.stack = false });
+        assert_eq!((m.ram[0x40], m.cpu.fault), (uWord::lit(0x2a), Some(Fault::Protection(Address::try_from(0x7f).unwrap()))));
+        let m = run(source, 2, |m| { m.map.on_violation = OnViolation::Fault; m.map.permissions.io = false });
+        assert_eq!((m.ram[0x40], m.cpu.fault), (uWord::ZERO, Some(Fault::Protection(Address::try_from(0x40).unwrap()))));
+    }
+
+    #[test]
+    fn stack() {
+        let m = run(&"psh #01\n".repeat(6), 6, |_| ());
+        assert_eq!((m.stack_depth(), m.cpu.fault), (Some(6), None));
+        let m = run(&"psh #01\n".repeat(7), 7, |_| ());
+        assert_eq!((m.stack_depth(), m.cpu.fault), (Some(6), Some(Fault::StackOverflow)));
+        let m = run("psh #01\npop a\npop a", 3, |_| ());
+        assert_eq!((m.stack_depth(), m.cpu.fault), (Some(0), Some(Fault::StackUnderflow)));
+        // Calls take two words each: the fourth does not fit, and changes nothing
+        let m = run(":f\ncal f", 4, |_| ());
+        assert_eq!((m.stack_depth(), m.cpu.fault), (Some(6), Some(Fault::StackOverflow)));
+        assert_eq!(m.cpu.pc, Address::try_from(0x80).unwrap());
+        let m = run("ret", 1, |_| ());
+        assert_eq!(m.cpu.fault, Some(Fault::StackUnderflow));
+    }
+
+    #[test]
+    fn timing() {
+        let run_in = |unit: TimeUnit, source: &str, steps: usize| {
+            let mut universe = Universe::new(UniverseConfig::default());
+            universe.unit = unit;
+            assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+            let mut modules = ModuleCollection::new(vec![]);
+            for _ in 0..steps { step_one(&mut universe, &mut modules).unwrap(); }
+            universe
+        };
+        // 3 + 5 + 2 cycles
+        let universe = run_in(TimeUnit::Instructions, "mov #05 a\nmov a %0001\nhcf", 3);
+        assert_eq!((universe.t, universe.cycles()), (3, 10));
+        let universe = run_in(TimeUnit::Cycles, "mov #05 a\nmov a %0001\nhcf", 10);
+        assert_eq!((universe.t, universe.cycles()), (10, 10));
+        assert!(universe.now().cpu.halted);
+        // The read takes 9 cycles, so the write lands 10 cycles after it
+        let source = |n| format!("mov %0001@+{} a\nmov #07 %0001\nhcf", n);
+        let universe = run_in(TimeUnit::Cycles, &source(10), 20);
+        assert_eq!(universe.now().cpu.a.value(), 0x07);
+        let universe = run_in(TimeUnit::Cycles, &source(9), 20);
+        assert_eq!(universe.now().cpu.a.value(), 0x00);
+    }
+
+    #[test]
+    fn pending_after_rewind() {
+        // The write into the future lands at t=2, then the read of %0001 at
+        // t=4 rewinds to t=1: the write was issued before that, and must land
+        // again
+        let source = "mov #07 %0101@+1\nmov %0001@+3 a\nnop\nmov #01 %0001\nhcf";
+        let m = run(source, 5, |_| ());
+        assert_eq!((m.cpu.a.value(), m.ram[0x41].value()), (0x01, 0x07));
+        assert!(m.cpu.halted);
+    }
+
+    #[test]
+    fn jump_limit() {
+        let config = UniverseConfig::parse("jump=80").unwrap();
+        assert_eq!((config.max_jump, config.window), (80, 320));
+        assert!(UniverseConfig::parse("jump=80,window=80").is_none());
+        // Assembled for the default limit, run with a shorter one
+        let mut universe = Universe::new(config);
+        assemble_into(universe.now_mut(), "mov %0001@+81 a\nhcf", MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        let result = step_one(&mut universe, &mut modules);
+        assert!(matches!(result, Err(EmuError::JumpOutOfRange { offset: 81, max: 80, .. })));
+    }
+
+    #[test]
+    fn halt() {
+        // The write lands two steps after the halt
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), "mov #05 %0001@+3\nhcf", MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        let mut popped = vec![];
+        loop {
+            let (machine, outcome) = step(&mut universe, &mut modules).unwrap();
+            popped.push(machine);
+            if outcome == StepOutcome::Halted { break }
+        }
+        // Does not fill the window with a halted machine
+        assert_lt!(popped.len(), 10);
+        let last = popped.last().unwrap();
+        assert!(last.cpu.halted);
+        assert_eq!(last.ram[0x40], uWord::lit(0x05));
+        // Halted is reported from then on
+        let (machine, outcome) = step(&mut universe, &mut modules).unwrap();
+        assert_eq!(outcome, StepOutcome::Halted);
+        assert_eq!(&machine, last);
+    }
+
+    /// Steps `source` with `strategy` until it halts, and returns the final state
+    fn run_with(source: &str, strategy: Box<dyn ConsistencyStrategy>) -> Result<Machine, EmuError> {
+        let mut universe = Universe::new(UniverseConfig::default());
+        universe.strategy = strategy;
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        loop {
+            if let (machine, StepOutcome::Halted) = step(&mut universe, &mut modules)? {
+                return Ok(machine)
+            }
+        }
+    }
+
+    #[test]
+    fn strategies() {
+        // Writes back 2 - x: consistent with x = 1 or 33, but naive iteration
+        // from 0 alternates 0, 2, 0, ...
+        let source = "mov %0001@+4 a\nnot a\nadd #03 a\nmov a %0001\nhcf";
+        assert!(matches!(run_with(source, Box::new(Naive)), Err(EmuError::Paradox { .. })));
+        let strategies: Vec<Box<dyn ConsistencyStrategy>> = vec![
+            Box::new(Seeded(uWord::lit(0x21))),
+            Box::new(Exhaustive::default()),
+            Box::new(Restarts::new(4, 1)),
+        ];
+        for strategy in strategies {
+            let value = run_with(source, strategy).unwrap().ram[0x40].value();
+            assert!(value == 0x01 || value == 0x21, "{:02x}", value);
+        }
+        assert!(parse_strategy("seed:2a").is_some());
+        assert!(parse_strategy("random:0").is_none());
+        assert!(parse_strategy("exhaustive:3").is_none());
+    }
+
+    fn signed(x: i8) -> uWord {
+        iWord::try_from(x).unwrap().as_uword()
+    }
+
+    #[test]
+    fn xch_registers() {
+        let m = run("xch a bl", 1, |m| {
+            m.cpu.a = uWord::lit(0x05);
+            m.cpu.bl = uWord::lit(0x2a);
+            m.cpu.flags.write(Flag::Z, true);
+        });
+        assert_eq!(m.cpu.a, uWord::lit(0x2a));
+        assert_eq!(m.cpu.bl, uWord::lit(0x05));
+        // Flags are untouched
+        assert!(m.cpu.flags.read(Flag::Z));
+        assert_eq!(m.cpu.fault, None);
+    }
+
+    #[test]
+    fn xch_memory() {
+        let m = run("xch %0001 (b)", 1, |m| {
+            m.cpu.bh = uWord::lit(0x01);
+            m.cpu.bl = uWord::lit(0x01);
+            m.ram[0x40] = uWord::lit(0x11);
+            m.ram[0x41] = uWord::lit(0x22);
+        });
+        assert_eq!(m.ram[0x40], uWord::lit(0x22));
+        assert_eq!(m.ram[0x41], uWord::lit(0x11));
+    }
+
+    #[test]
+    fn xch_same_operand() {
+        let m = run("xch a a", 1, |m| m.cpu.a = uWord::lit(0x17));
+        assert_eq!(m.cpu.a, uWord::lit(0x17));
+    }
+
+    #[test]
+    fn xch_future() {
+        // bl gets the word at 0040 as it is one step ahead (before the write
+        // lands), and 0040 gets bl
+        let m = run("xch %0001@+1 bl\nnop\nnop", 3, |m| {
+            m.cpu.bl = uWord::lit(0x03);
+        });
+        assert_eq!(m.cpu.bl, uWord::ZERO);
+        assert_eq!(m.ram[0x40], uWord::lit(0x03));
+    }
+
+    #[test]
+    fn xch_read_only() {
+        // Neither write happens, whichever faults
+        let mut flags = Cpu::default().flags;
+        flags.write(Flag::C, true);
+        for source in ["xch #01 a", "xch a #01", "xch f a"] {
+            let m = run(source, 1, |m| {
+                m.cpu.a = uWord::lit(0x05);
+                m.cpu.flags.write(Flag::C, true);
+            });
+            assert_eq!(m.cpu.fault, Some(Fault::ReadOnly), "{}", source);
+            assert_eq!(m.cpu.a, uWord::lit(0x05), "{}", source);
+            assert_eq!(m.cpu.flags.word(), flags.word(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn xch_protected() {
+        let m = run("xch a %0000", 1, |m| {
+            m.cpu.a = uWord::lit(0x05);
+            m.ram[0x00] = uWord::lit(0x2a);
+            m.map.on_violation = OnViolation::Fault;
+        });
+        assert_eq!(m.cpu.fault, Some(Fault::Protection(Address::ZERO)));
+        assert_eq!((m.cpu.a, m.ram[0x00]), (uWord::lit(0x05), uWord::lit(0x2a)));
+        assert_eq!(m.cpu.flags.word(), uWord::ZERO);
+    }
+
+    #[test]
+    fn fault_ends_instruction() {
+        // No flags are set after a write that faults, and nothing is popped
+        let m = run("mov #00 #01", 1, |_| ());
+        assert_eq!((m.cpu.fault, m.cpu.flags.word()), (Some(Fault::ReadOnly), uWord::ZERO));
+        let m = run("psh #01\npop #02", 2, |_| ());
+        assert_eq!((m.cpu.fault, m.stack_depth()), (Some(Fault::ReadOnly), Some(1)));
+    }
+
+    #[test]
+    fn dis_mos() {
+        // (dst, src, dst ÷ src, dst mod src)
+        let table: [(i8, i8, i8, i8); 8] = [
+            (7, 2, 3, 1),
+            (-7, 2, -3, -1),
+            (7, -2, -3, 1),
+            (-7, -2, 3, -1),
+            (0, 5, 0, 0),
+            (31, 31, 1, 0),
+            (-32, 1, -32, 0),
+            // Overflows and wraps around
+            (-32, -1, -32, 0),
+        ];
+        for (dst, src, quot, rem) in table {
+            let setup = |m: &mut Machine| {
+                m.cpu.a = signed(dst);
+                m.cpu.bl = signed(src);
+            };
+            let m = run("dis bl a", 1, setup);
+            assert_eq!(m.cpu.a, signed(quot), "{} ÷ {}", dst, src);
+            assert_eq!(m.cpu.flags.read(Flag::Z), quot == 0);
+            assert_eq!(m.cpu.flags.read(Flag::N), quot < 0);
+            assert!(!m.cpu.flags.read(Flag::C));
+            let m = run("mos bl a", 1, setup);
+            assert_eq!(m.cpu.a, signed(rem), "{} mod {}", dst, src);
+            assert_eq!(m.cpu.flags.read(Flag::Z), rem == 0);
+            assert_eq!(m.cpu.flags.read(Flag::N), rem < 0);
+            assert!(!m.cpu.flags.read(Flag::C));
+        }
+    }
+
+    #[test]
+    fn dis_mos_by_zero() {
+        for source in ["dis bl a", "mos bl a"] {
+            let m = run(source, 1, |m| {
+                m.cpu.a = uWord::lit(0x2a);
+                m.cpu.flags.write(Flag::N, true);
+            });
+            assert_eq!(m.cpu.a, uWord::lit(0x2a));
+            assert!(m.cpu.flags.read(Flag::C));
+            assert!(m.cpu.flags.read(Flag::N));
+            assert_eq!(m.cpu.fault, None);
+        }
+    }
+
+    #[test]
+    fn div_mod_table() {
+        for carry in [false, true] {
+            for dst in 0..=0x3f_u8 {
+                for src in 0..=0x3f_u8 {
+                    let setup = |m: &mut Machine| {
+                        m.cpu.a = uWord::lit(dst);
+                        m.cpu.bl = uWord::lit(src);
+                        m.cpu.flags.write(Flag::C, carry);
+                        // Z starts out wrong, to tell apart a flag left untouched
+                        m.cpu.flags.write(Flag::Z, dst != 0);
+                    };
+                    let div = run("div bl a", 1, setup);
+                    let rem = run("mod bl a", 1, setup);
+                    let dividend = dst as u16 + ((carry as u16) << 6);
+                    let case = format!("C={} dst={:02x} src={:02x}", carry as u8, dst, src);
+                    if src == 0 {
+                        for m in [div, rem] {
+                            assert_eq!(m.cpu.a, uWord::lit(dst), "{}", case);
+                            assert!(m.cpu.flags.read(Flag::C), "{}", case);
+                            assert_eq!(m.cpu.flags.read(Flag::Z), dst != 0, "{}", case);
+                        }
+                    } else {
+                        let quotient = dividend / src as u16;
+                        let remainder = dividend % src as u16;
+                        assert_eq!(div.cpu.a.value() as u16, quotient % 64, "{}", case);
+                        assert_eq!(div.cpu.flags.read(Flag::Z), quotient % 64 == 0, "{}", case);
+                        assert_eq!(div.cpu.flags.read(Flag::C), quotient >= 64, "{}", case);
+                        assert_eq!(rem.cpu.a.value() as u16, remainder, "{}", case);
+                        assert_eq!(rem.cpu.flags.read(Flag::Z), remainder == 0, "{}", case);
+                        assert!(!rem.cpu.flags.read(Flag::C), "{}", case);
+                    }
+                }
+            }
+        }
+    }
+
+    // ALU conformance: every instruction that computes a value, against a 
+    // reference model, over every operand and flag value //
+
+    /// Runs `instruction` on a machine with A = `dst`, BL = `src`, and 
+    /// the given flags
+    fn execute_on(instruction: &Instruction, src: u8, dst: u8, flags: u8) -> Machine {
+        let mut universe = Universe::new(UniverseConfig::default());
+        let m = universe.now_mut();
+        m.cpu.a = uWord::lit(dst);
+        m.cpu.bl = uWord::lit(src);
+        for (flag, bit) in [(Flag::N, 0), (Flag::V, 1), (Flag::Z, 2), (Flag::C, 3)] {
+            m.cpu.flags.write(flag, flags & (1 << bit) != 0);
+        }
+        universe.push_new_state().unwrap();
+        execute(&mut universe, instruction).unwrap();
+        universe.now().clone()
+    }
+
+    /// Reference model: from `src`, `dst` and the flags (bits as in F) to 
+    /// the word written to dst (if any) and the new flags
+    fn reference(name: &str, src: u8, dst: u8, flags: u8) -> (Option<u8>, u8) {
+        const N: u8 = 1 << 0;
+        const V: u8 = 1 << 1;
+        const Z: u8 = 1 << 2;
+        const C: u8 = 1 << 3;
+        let signed = |x: u8| if x >= 32 { x as i32 - 64 } else { x as i32 };
+        let fits = |x: i32| (-32..32).contains(&x);
+        let (s, d, c) = (src as i32, dst as i32, (flags & C != 0) as i32);
+        let (sd, ss) = (signed(dst), signed(src));
+
+        // (exact result, whether it is written, flags it updates, C, V)
+        let (result, write, mask, carry, overflow) = match name {
+            "mov" => (s, true, N|V|Z, false, false),
+            "add" => (d + s + c, true, N|V|Z|C, d + s + c > 63, !fits(sd + ss + c)),
+            "sub" => (d - s - (1-c), true, N|V|Z|C, d - s - (1-c) >= 0, !fits(sd - ss - (1-c))),
+            "cmp" => (d - s - (1-c), false, N|V|Z|C, d - s - (1-c) >= 0, !fits(sd - ss - (1-c))),
+            "mul" => (d * s, true, N|V|Z, false, false),
+            "muh" => ((d * s) >> 6, true, N|V|Z, false, false),
+            "mus" => ((sd * ss) >> 6, true, N|V|Z, false, false),
+            "div" | "mod" | "dis" | "mos" if s == 0 => (0, false, C, true, false),
+            "div" => ((d + 64*c) / s, true, Z|C, (d + 64*c) / s > 63, false),
+            "mod" => ((d + 64*c) % s, true, Z|C, false, false),
+            "dis" => (sd / ss, true, N|Z|C, false, false),
+            "mos" => (sd % ss, true, N|Z|C, false, false),
+            "and" => (d & s, true, N|V|Z, false, false),
+            "or"  => (d | s, true, N|V|Z, false, false),
+            "xor" => (d ^ s, true, N|V|Z, false, false),
+            "bit" => (d & s, false, N|V|Z, false, false),
+            "not" => (!d, true, N|V|Z, false, false),
+            "lsl" => (d << 1, true, N|V|Z|C, d & 32 != 0, !fits(sd * 2)),
+            "lsr" => ((d >> 1) | (c << 5), true, N|V|Z|C, d & 1 != 0, false),
+            "asr" => (sd >> 1, true, N|V|Z|C, d & 1 != 0, false),
+            "inc" => (d + 1, true, N|V|Z, false, !fits(sd + 1)),
+            "dec" => (d - 1, true, N|V|Z, false, !fits(sd - 1)),
+            _ => unreachable!(),
+        };
+        let word = result.rem_euclid(64) as u8;
+        let mut new = 0;
+        if word & 32 != 0 { new |= N }
+        if overflow { new |= V }
+        if word == 0 { new |= Z }
+        if carry { new |= C }
+        let flags = (flags & !mask) | (new & mask);
+        (if write { Some(word) } else { None }, flags)
+    }
+
+    fn check(instruction: Instruction, src: u8, dst: u8, flags: u8) {
+        let (result, expected_flags) = reference(instruction.name(), src, dst, flags);
+        let m = execute_on(&instruction, src, dst, flags);
+        let case = format!("{} src={:02x} dst={:02x} NVZC={:04b}", instruction, src, dst, flags);
+        assert_eq!(m.cpu.a.value(), result.unwrap_or(dst), "{}", case);
+        assert_eq!(m.cpu.flags.word().value(), expected_flags, "{}", case);
+    }
+
+    #[test]
+    fn alu_binary() {
+        use Instruction::*;
+        let register = |r| Timed { op: Op::Reg(r), time: iLong::ZERO };
+        let ops = Operands { src: register(Register::BL), dst: register(Register::A) };
+        for f in [Mov, Add, Sub, Mul, Muh, Mus, Div, Dis, Mod, Mos, And, Or, Xor, Cmp, Bit] {
+            let instruction = f(ops.clone());
+            for flags in 0..16 {
+                for src in 0..64 {
+                    for dst in 0..64 {
+                        check(instruction.clone(), src, dst, flags);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alu_unary() {
+        use Instruction::*;
+        let op = Timed { op: Op::Reg(Register::A), time: iLong::ZERO };
+        for f in [Not, Lsl, Lsr, Asr, Inc, Dec] {
+            let instruction = f(op.clone());
+            for flags in 0..16 {
+                for dst in 0..64 {
+                    check(instruction.clone(), 0, dst, flags);
+                }
+            }
+        }
+    }
+}
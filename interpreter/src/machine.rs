@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use std::rc::Rc;
 
 // CPU //
 
@@ -23,6 +24,11 @@ impl From<Flag> for u8 {
 pub struct FlagWord (uWord);
 
 impl FlagWord {
+    /// The whole flag register, as read by the `f` operand
+    pub fn word(&self) -> uWord {
+        self.0
+    }
+
     pub fn read(&self, flag: Flag) -> bool {
         let mask = u8::from(flag);
         u8::from(self.0) & mask != 0
@@ -41,6 +47,37 @@ impl FlagWord {
 
 pub type Address = uLong;
 
+/// Condition that stops the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The words at PC are not a valid instruction
+    Decode(DecodeError),
+    /// The instruction writes to a read-only operand (the flags, or an
+    /// immediate)
+    ReadOnly,
+    /// The instruction writes to a location the memory map protects
+    Protection(Address),
+    /// Push onto a full stack
+    StackOverflow,
+    /// Pop from an empty stack
+    StackUnderflow,
+    /// A time jump of this offset, farther than the power supply can feed
+    BrownOut(isize),
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::Decode(x) => write!(f, "{}", x),
+            Fault::ReadOnly => write!(f, "write to read-only operand"),
+            Fault::Protection(x) => write!(f, "write to protected location {}", Op::Abs(*x)),
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "stack underflow"),
+            Fault::BrownOut(x) => write!(f, "brown-out on a jump of {:+}", x),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 #[derive(PartialEq, Eq)]
 pub struct Cpu {
@@ -53,6 +90,14 @@ pub struct Cpu {
     pub x: uWord,
     pub sp: Address,
     pub pc: Address,
+    /// Set by HCF, after which the CPU does nothing
+    pub halted: bool,
+    /// Set when the CPU faults, after which it halts (with PC at the faulting
+    /// instruction)
+    pub fault: Option<Fault>,
+    /// Cycles left of the instruction in progress, while time counts cycles
+    /// (see [`crate::timing::TimeUnit`])
+    pub busy: u8,
 }
 
 impl Default for Cpu {
@@ -69,6 +114,9 @@ impl Default for Cpu {
             x: Default::default(),
             sp,
             pc,
+            halted: false,
+            fault: None,
+            busy: 0,
         }
     }
 }
@@ -76,21 +124,62 @@ impl Default for Cpu {
 // RAM //
 
 const RAM_SIZE: usize = Address::MAX.value() as usize + 1;
+/// Words in a page of RAM
+const PAGE_SIZE: usize = 64;
 
+type Page = [uWord; PAGE_SIZE];
+
+/// RAM, in pages that are shared between clones: cloning copies a pointer per
+/// page, and a shared page is only copied when written (copy on write).
 #[derive(Clone, Eq)]
-pub struct Ram(pub Vec<uWord>);
+pub struct Ram {
+    pages: Vec<Rc<Page>>,
+    /// One past the highest word written
+    end: usize,
+}
+
+// To save space, pages are only kept up to the highest word written. Memory 
+// past them is assumed to be 0.
+impl Ram {
+    /// One past the highest word written: the words that make up a dump
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Words from 0 up to [`Ram::end`]
+    pub fn iter(&self) -> impl Iterator<Item = uWord> + '_ {
+        (0..self.end).map(move |i| self[i])
+    }
+
+    /// Copy of the words in `range`
+    pub fn read(&self, range: std::ops::Range<usize>) -> Vec<uWord> {
+        range.map(|i| self[i]).collect()
+    }
+
+    /// Writes `words`, from `start` on
+    pub fn write(&mut self, start: usize, words: &[uWord]) {
+        for (i, word) in words.iter().enumerate() {
+            self[start + i] = *word;
+        }
+    }
+
+    /// Addresses where `self` and `other` differ. Pages still shared between 
+    /// them are not looked at.
+    pub fn changes<'a>(&'a self, other: &'a Ram) -> impl Iterator<Item = usize> + 'a {
+        let pages = std::cmp::max(self.pages.len(), other.pages.len());
+        (0..pages)
+            .filter(move |&i| match (self.pages.get(i), other.pages.get(i)) {
+                (Some(a), Some(b)) => !Rc::ptr_eq(a, b),
+                _ => true,
+            })
+            .flat_map(|i| i*PAGE_SIZE..(i+1)*PAGE_SIZE)
+            .filter(move |&i| self[i] != other[i])
+    }
+}
 
-// To save space, RAM is represented by a vector with size ≤ RAM_SIZE. Memory  
-// past the size of the vector is assumed to be 0.
 impl PartialEq for Ram {
     fn eq(&self, other: &Self) -> bool {
-        let (min, max) = if self.0.len() < other.0.len() {
-            (&self.0, &other.0)
-        } else {
-            (&other.0, &self.0)
-        };
-        min[..min.len()] == max[..min.len()]
-        && max[min.len()..max.len()].iter().all(|x| x == &uWord::ZERO)
+        self.changes(other).next().is_none()
     }
 }
 
@@ -105,8 +194,10 @@ impl std::ops::Index<usize> for Ram {
 
     fn index(&self, index: usize) -> &Self::Output {
         debug_assert_lt!(index, RAM_SIZE);
-        if index >= self.0.len() { return &uWord::ZERO };
-        &self.0[index]
+        match self.pages.get(index / PAGE_SIZE) {
+            Some(page) => &page[index % PAGE_SIZE],
+            None => &uWord::ZERO,
+        }
     }
 }
 
@@ -121,8 +212,14 @@ impl std::ops::Index<Address> for Ram {
 impl std::ops::IndexMut<usize> for Ram {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         debug_assert_lt!(index, RAM_SIZE);
-        if index >= self.0.len() { self.0.resize(index+1, uWord::ZERO) };
-        &mut self.0[index]
+        let page = index / PAGE_SIZE;
+        if page >= self.pages.len() {
+            // New pages all share one zero page, until written
+            let zero = Rc::new([uWord::ZERO; PAGE_SIZE]);
+            self.pages.resize_with(page+1, || zero.clone());
+        }
+        self.end = self.end.max(index+1);
+        &mut Rc::make_mut(&mut self.pages[page])[index % PAGE_SIZE]
     }
 }
 
@@ -132,40 +229,164 @@ impl std::ops::IndexMut<Address> for Ram {
     }
 }
 
-impl std::ops::Index<std::ops::Range<usize>> for Ram {
-    type Output = [uWord];
+impl From<Vec<uWord>> for Ram {
+    fn from(words: Vec<uWord>) -> Self {
+        let mut ram = Ram { pages: vec![], end: 0 };
+        ram.write(0, &words);
+        ram
+    }
+}
 
-    fn index(&self, range: std::ops::Range<usize>) -> &Self::Output {
-        debug_assert_le!(range.end, self.0.len()); //TODO
-        &self.0[range]
+impl Default for Ram {
+    fn default() -> Self {
+        Ram { pages: vec![], end: 64*2 }
     }
 }
 
-impl std::ops::Index<std::ops::Range<Address>> for Ram {
-    type Output = [uWord];
+// Memory map //
+
+pub const RESERVED: std::ops::Range<usize> = 0x00..0x10;
+pub const STACK: std::ops::Range<usize> = 0x70..0x80;
+pub const EPROM_START: usize = 0x80;
+
+/// The regions of memory, as in the manual: reserved, IO, stack, then the 
+/// EPROM, and RAM after it. The EPROM ends where programming mode stopped
+/// writing, so a program can keep its data right after its code (and a stray
+/// write just past the code is not a violation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Reserved,
+    Io,
+    Stack,
+    Eprom,
+    Ram,
+}
 
-    fn index(&self, range: std::ops::Range<Address>) -> &Self::Output {
-        &self[usize::from(range.start)..usize::from(range.end)]
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Region::Reserved => "reserved region",
+            Region::Io => "IO window",
+            Region::Stack => "stack",
+            Region::Eprom => "EPROM",
+            Region::Ram => "RAM",
+        };
+        write!(f, "{}", name)
     }
 }
 
-impl std::ops::IndexMut<std::ops::Range<usize>> for Ram {
-    fn index_mut(&mut self, range: std::ops::Range<usize>) -> &mut Self::Output {
-        debug_assert_le!(range.end, RAM_SIZE);
-        if range.end >= self.0.len() { self.0.resize(range.end, uWord::ZERO) };
-        &mut self.0[range]
+/// Whether the CPU may write each region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub reserved: bool,
+    pub io: bool,
+    pub stack: bool,
+    pub eprom: bool,
+    pub ram: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        // Reprogramming the EPROM takes programming mode
+        Permissions { reserved: false, io: true, stack: true, eprom: false, ram: true }
     }
 }
 
-impl std::ops::IndexMut<std::ops::Range<Address>> for Ram {
-    fn index_mut(&mut self, range: std::ops::Range<Address>) -> &mut Self::Output {
-        &mut self[usize::from(range.start)..usize::from(range.end)]
+impl Permissions {
+    pub fn writable(&self, region: Region) -> bool {
+        match region {
+            Region::Reserved => self.reserved,
+            Region::Io => self.io,
+            Region::Stack => self.stack,
+            Region::Eprom => self.eprom,
+            Region::Ram => self.ram,
+        }
     }
 }
 
-impl Default for Ram {
+/// What happens when the CPU writes where it may not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnViolation {
+    /// The write goes through silently (some programs modify their own code)
+    Ignore,
+    /// The write goes through, and is reported on stderr
+    Log,
+    /// The write does not happen, and the CPU faults
+    Fault,
+}
+
+/// Where each region lies, and what the CPU may write. Programming mode can
+/// write anywhere (see [`Machine::program`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// End of the EPROM, which is as far as it has been programmed
+    pub eprom_end: usize,
+    pub permissions: Permissions,
+    pub on_violation: OnViolation,
+    programming: bool,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap { 
+            eprom_end: EPROM_START, 
+            permissions: Permissions::default(), 
+            on_violation: OnViolation::Log, 
+            programming: false,
+        }
+    }
+}
+
+impl MemoryMap {
+    pub fn region(&self, address: usize) -> Region {
+        match address {
+            x if RESERVED.contains(&x) => Region::Reserved,
+            x if IO_WINDOW.contains(&x) => Region::Io,
+            x if STACK.contains(&x) => Region::Stack,
+            x if (EPROM_START..self.eprom_end).contains(&x) => Region::Eprom,
+            _ => Region::Ram,
+        }
+    }
+
+    pub fn is_writable(&self, address: usize) -> bool {
+        self.programming || self.permissions.writable(self.region(address))
+    }
+}
+
+// Stack //
+
+/// The stack grows down from `top`, and holds `size` words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    pub top: Address,
+    pub size: usize,
+}
+
+impl Default for StackBounds {
     fn default() -> Self {
-        Self(vec![uWord::ZERO; 64*2])
+        // Six words from $7f, as in the manual
+        StackBounds { top: Cpu::default().sp, size: 6 }
+    }
+}
+
+// Bus //
+
+/// Memory locations mapped to IO devices (see [`crate::modules`])
+pub const IO_WINDOW: std::ops::Range<usize> = 0x10..0x70;
+
+/// An access by the CPU to a memory-mapped IO location, in the state at time
+/// `t` (which, with a time offset, is not the present)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusAccess {
+    Read { t: usize, address: Address },
+    Write { t: usize, address: Address, value: uWord },
+}
+
+impl BusAccess {
+    pub fn address(&self) -> Address {
+        match self {
+            BusAccess::Read { address, .. } | BusAccess::Write { address, .. } => *address,
+        }
     }
 }
 
@@ -176,6 +397,12 @@ impl Default for Ram {
 pub struct Machine {
     pub ram: Ram,
     pub cpu: Cpu,
+    pub map: MemoryMap,
+    pub stack: StackBounds,
+    /// Clock cycles elapsed up to this state
+    pub cycles: u64,
+    /// Energy drawn by the Novikov module up to this state, in mJ
+    pub energy: u64,
 }
 
 impl Machine {
@@ -183,6 +410,10 @@ impl Machine {
         Machine {
             ram: Ram::default(),
             cpu: Cpu::default(),
+            map: MemoryMap::default(),
+            stack: StackBounds::default(),
+            cycles: 0,
+            energy: 0,
         }
     }
 
@@ -198,26 +429,75 @@ impl Machine {
         word
     }
 
-    /// Write a word at PC and increment the PC.
+    /// Write a word at PC and increment the PC (in programming mode, so this
+    /// can write the EPROM).
     pub fn write_pc(&mut self, word: uWord) {
-        self.ram[self.cpu.pc] = word;
+        debug_assert!(self.map.programming, "write_pc outside programming mode");
+        let address = usize::from(self.cpu.pc);
+        self.ram[address] = word;
+        if address >= EPROM_START { self.map.eprom_end = self.map.eprom_end.max(address + 1) };
         self.increment_pc();
+    }
 
+    /// Runs `f` in programming mode, where anything can be written: what it
+    /// writes from $80 on becomes the EPROM.
+    pub fn program(&mut self, f: impl FnOnce(&mut Self)) {
+        self.map.programming = true;
+        f(self);
+        self.map.programming = false;
     }
 
-    /// Read a word from stack and increment the sp. 
-    pub fn read_sp(&mut self) -> uWord {
-        self.cpu.sp = self.cpu.sp + 1_i8;
-        let word = self.ram[self.cpu.sp];
-        word
+    /// Fails if a write to `address` by the CPU would fault, without writing
+    pub fn check_store(&self, address: Address) -> Result<(), Fault> {
+        match self.map.is_writable(usize::from(address)) || self.map.on_violation != OnViolation::Fault {
+            true => Ok(()),
+            false => Err(Fault::Protection(address)),
+        }
+    }
+
+    /// Writes a word to memory as the CPU does, following the memory map
+    pub fn store(&mut self, address: Address, value: uWord) -> Result<(), Fault> {
+        self.check_store(address)?;
+        if !self.map.is_writable(usize::from(address)) && self.map.on_violation == OnViolation::Log {
+            let region = self.map.region(usize::from(address));
+            eprintln!("Write of {:02x} to protected location {} ({}).", value.value(), Op::Abs(address), region)
+        }
+        self.ram[address] = value;
+        Ok(())
+    }
+
+    /// Words on the stack, or None if SP was moved out of it
+    pub fn stack_depth(&self) -> Option<usize> {
+        let (top, sp) = (usize::from(self.stack.top), usize::from(self.cpu.sp));
+        top.checked_sub(sp).filter(|&x| x <= self.stack.size)
+    }
+
+    /// Write words to stack, decrementing the sp, if they all fit. 
+    pub fn push(&mut self, words: &[uWord]) -> Result<(), Fault> {
+        match self.stack_depth() {
+            Some(depth) if depth + words.len() <= self.stack.size => (),
+            _ => return Err(Fault::StackOverflow),
+        }
+        for word in words {
+            self.ram[self.cpu.sp] = *word;
+            self.cpu.sp = self.cpu.sp + (-1_i8);
+        }
+        Ok(())
     }
 
-    /// Write a word to stack and decrement the sp. 
-    pub fn write_sp(&mut self, word: uWord) {
-        self.ram[self.cpu.sp] = word;
-        self.cpu.sp = self.cpu.sp + (-1_i8);
+    /// Read `N` words from stack, incrementing the sp, if there are that many.
+    pub fn pop<const N: usize>(&mut self) -> Result<[uWord; N], Fault> {
+        match self.stack_depth() {
+            Some(depth) if depth >= N => (),
+            _ => return Err(Fault::StackUnderflow),
+        }
+        Ok(std::array::from_fn(|_| {
+            self.cpu.sp = self.cpu.sp + 1_i8;
+            self.ram[self.cpu.sp]
+        }))
     }
 
+    #[cfg(test)]
     pub fn reset_cpu(&mut self) {
         self.cpu = Cpu::default();
     }
@@ -237,6 +517,12 @@ impl std::fmt::Display for Machine {
             self.cpu.sp.hi().value(), self.cpu.sp.lo().value(),
             self.cpu.pc.hi().value(), self.cpu.pc.lo().value(),
         ).unwrap();
+        if self.cpu.halted {
+            write!(f, "Halted\n").unwrap();
+        }
+        if let Some(fault) = self.cpu.fault {
+            write!(f, "Fault: {}\n", fault).unwrap();
+        }
         write!(f, "Mem: ").unwrap();
         for j in 0..64 { write!(f, "{:02x} ", j).unwrap() };
         write!(f, "\n").unwrap();
@@ -269,3 +555,49 @@ impl std::fmt::Display for Machine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ram_pages() {
+        let mut a = Ram::default();
+        a[0xfff] = uWord::lit(0x01);
+        let mut b = a.clone();
+        b[0x400] = uWord::lit(0x02);
+        assert_eq!((a[0x400], b[0x400], b[0xfff]), (uWord::ZERO, uWord::lit(0x02), uWord::lit(0x01)));
+        assert_eq!(b.changes(&a).collect::<Vec<_>>(), vec![0x400]);
+        // Memory past the end reads as 0
+        b[0x400] = uWord::ZERO;
+        assert_eq!(a, b);
+        assert_eq!(Ram::from(vec![uWord::ZERO; 3]), Ram::default());
+    }
+
+    #[test]
+    fn memory_map() {
+        // Each region, with its default permission, after 15 words of EPROM
+        let mut m = Machine::new();
+        m.program(|m| for _ in 0..15 { m.write_pc(uWord::ZERO) });
+        m.map.on_violation = OnViolation::Fault;
+        for (address, region, writable) in [
+            (0x00, Region::Reserved, false), (0x0f, Region::Reserved, false),
+            (0x10, Region::Io, true), (0x6f, Region::Io, true),
+            (0x70, Region::Stack, true), (0x7f, Region::Stack, true),
+            (0x80, Region::Eprom, false), (0x8e, Region::Eprom, false),
+            (0x8f, Region::Ram, true), (0xfff, Region::Ram, true),
+        ] {
+            assert_eq!((m.map.region(address), m.map.is_writable(address)), (region, writable), "{:x}", address);
+            let address = Address::try_from(address as u16).unwrap();
+            let expected = if writable { Ok(()) } else { Err(Fault::Protection(address)) };
+            assert_eq!(m.clone().store(address, uWord::lit(0x2a)), expected);
+        }
+        // The EPROM ends with the last word programmed, and RAM starts right
+        // after it, until more is programmed
+        let address = Address::try_from(0x8f).unwrap();
+        assert_eq!(m.clone().store(address, uWord::lit(0x2a)), Ok(()));
+        m.program(|m| m.write_pc(uWord::ZERO));
+        assert_eq!(m.map.region(0x8f), Region::Eprom);
+        assert_eq!(m.clone().store(address, uWord::lit(0x2a)), Err(Fault::Protection(address)));
+    }
+}
@@ -1,13 +1,18 @@
 pub(crate) use crate::prelude::*;
+use crate::interpreter::{EmuError, StepOutcome};
 use crate::modules::{ClockModule, DisplayModule, DiskModule, ModuleCollection};
 
+mod analysis;
 mod assembler;
+mod inspect;
 mod instruction;
 mod interpreter;
 mod machine;
 mod modules;
+mod paradox;
+mod power;
 mod prelude;
-mod state;
+mod timing;
 mod universe;
 mod word;
 
@@ -19,6 +24,18 @@ fn main() -> std::io::Result<()> {
     std::io::stdin().read_to_string(&mut buffer)?;
     let buffer = buffer.to_lowercase(); // For flexibility
 
+    // For reading back punch cards or disk files: print the input memory dump
+    // (one word per token, binary or hex) as assembly, from the address in
+    // the DISASSEMBLE env. variable (`llhh`, default %0002) to the end.
+    if let Ok(start) = std::env::var("DISASSEMBLE") {
+        let start = match start.as_str() {
+            "" => assembler::ORIGIN,
+            x => usize::from(assembler::parse_address(x).and_then(Result::ok).expect("Invalid start address.")),
+        };
+        disassemble(&buffer, start);
+        return Ok(()); // Exit(0)
+    }
+
     // IO
     let mut io_modules = {
         let clock_module = ClockModule;
@@ -38,24 +55,108 @@ fn main() -> std::io::Result<()> {
         }
     };
 
+    // For reproducing a run: save what the IO modules do to the file in the
+    // RECORD env. variable, and/or take it from the file in REPLAY
+    if let Ok(path) = std::env::var("REPLAY") {
+        io_modules.log = std::mem::take(&mut io_modules.log).replay(path).expect("Could not read replay file.");
+    }
+    if let Ok(path) = std::env::var("RECORD") {
+        io_modules.log = std::mem::take(&mut io_modules.log).record(path).expect("Could not create record file.");
+    }
+
     // Compilation
 
-    let mut universe = Universe::new();
-    assembler::assemble_into(universe.now_mut(), buffer.as_str());
+    // Limits on time jumps, the timeline and the search for consistency: set 
+    // by the UNIVERSE env. variable, as `key=value` separated by commas (keys
+    // `jump`, `window`, `padding` and `iterations`; e.g. `jump=200` as in the
+    // manual)
+    let config = match std::env::var("UNIVERSE") {
+        Ok(config) => UniverseConfig::parse(&config).expect("Invalid UNIVERSE config."),
+        Err(_) => UniverseConfig::default(),
+    };
+    let mut universe = Universe::new(config);
+    // How to look for consistent timelines: set by the STRATEGY env. variable
+    // (`naive`, `seed:NN`, `exhaustive` or `random[:N]`, default naive)
+    if let Ok(strategy) = std::env::var("STRATEGY") {
+        universe.strategy = interpreter::parse_strategy(&strategy).expect("Invalid strategy.");
+    }
+    // What a time offset `@±n` counts: set by the TIMING env. variable
+    // (`instructions` or `cycles`, default instructions)
+    if let Ok(unit) = std::env::var("TIMING") {
+        universe.unit = timing::parse_unit(&unit).expect("Invalid TIMING unit.");
+    }
+    // What feeds the time jumps: set by the POWER env. variable (`mains`,
+    // `sps-3-5000` or `sps-3-6000`, then optionally `:fault` or `:degrade` for
+    // what a brown-out does; default mains, which never browns out)
+    if let Ok(supply) = std::env::var("POWER") {
+        universe.power = power::parse_power(&supply).expect("Invalid POWER supply.");
+    }
+    // For debugging: if execution fails (e.g. on a paradox), print when each
+    // location in the INSPECT env. variable (registers or `%llhh`, separated
+    // by commas) changed over the states still kept, and all that changed
+    // over them
+    let inspected = std::env::var("INSPECT").ok().map(|x| {
+        x.split(',').map(|x| inspect::parse_location(x).expect("Invalid INSPECT location.")).collect::<Vec<_>>()
+    });
+    let max_jump = universe.config.max_jump;
+    if let Err(errors) = assembler::assemble_into(universe.now_mut(), buffer.as_str(), max_jump) {
+        for error in errors { eprintln!("{}", error) };
+        std::process::exit(1);
+    }
+
+    // What to do on writes to the EPROM or the reserved region: set by the 
+    // PROTECT env. variable (`ignore`, `log` or `fault`, default log)
+    if let Ok(protect) = std::env::var("PROTECT") {
+        universe.now_mut().map.on_violation = match protect.as_str() {
+            "ignore" => OnViolation::Ignore,
+            "log" => OnViolation::Log,
+            "fault" => OnViolation::Fault,
+            _ => panic!("Invalid PROTECT mode."),
+        };
+    }
 
     // For printing the punch cards: write the compiled program in binary to a file
     // Do this if the PUNCHCARD env. variable is set.
     if let Ok(_) = std::env::var("PUNCHCARD") {
-        for word in universe.now().ram.0.iter() {
+        for word in universe.now().ram.iter() {
             println!("{:06b}" ,word.value());
         }
         return Ok(()); // Exit(0)
     }
 
+    // For checking temporal programs: list every consistent timeline, up to
+    // the number in the ENUMERATE env. variable (default 16), under the
+    // UNIVERSE, TIMING and POWER settings above, and exit
+    if let Ok(bound) = std::env::var("ENUMERATE") {
+        let mut bounds = analysis::Bounds::default();
+        if !bound.is_empty() { bounds.timelines = bound.parse().expect("Invalid bound.") };
+        match analysis::enumerate(&universe, &bounds) {
+            Ok(enumeration) => print!("{}", enumeration),
+            Err(err) => { eprintln!("Error: {}.", err); std::process::exit(1) }
+        }
+        return Ok(()); // Exit(0)
+    }
+
+    // For measuring the emulator: run without IO modules until the program
+    // halts, and report speed and peak memory on stderr. Do this if the BENCH
+    // env. variable is set.
+    if std::env::var("BENCH").is_ok() {
+        bench(universe);
+        return Ok(()); // Exit(0)
+    }
+
     // Emulation
 
-    io_modules.run(&mut universe);
+    if let Err(err) = io_modules.run(&mut universe) {
+        eprintln!("IO module failed: {}", err);
+        std::process::exit(1);
+    }
+
+    // To run in real time, at the nominal clock speed: set the THROTTLE env.
+    // variable
+    let throttle = std::env::var("THROTTLE").ok().map(|_| timing::Throttle::new(universe.cycles()));
 
+    let mut status = 0;
     for t in 0.. {
         // Run IO modules
         // @André: Não sei quais as consequências de não correr isto na fase de
@@ -66,16 +167,36 @@ fn main() -> std::io::Result<()> {
 
         {
             // Step the machine (auto loop)
-            let (machine, instruction) = interpreter::step(&mut universe, &mut io_modules).expect("Consistency failure.");
+            let (machine, outcome) = match interpreter::step(&mut universe, &mut io_modules) {
+                Ok(x) => x,
+                Err(EmuError::Fault(fault)) => { println!("Execution halted: {}.", fault); break }
+                Err(EmuError::Paradox { ti, tf, report }) => {
+                    eprintln!("Error: paradox, no consistent timeline for t={}..{}.", ti, tf);
+                    eprint!("{}", report);
+                    status = 1;
+                    break
+                }
+                Err(err) => { eprintln!("Error: {}.", err); status = 1; break }
+            };
+
+            if let Some(throttle) = &throttle { throttle.wait(machine.cycles) };
 
             println!("t = {}", t);
-            println!("instruction: {:?}", instruction);
+            println!("outcome: {:?}", outcome);
             println!("{}", machine);
 
-            if let Instruction::Hcf = instruction { println!("Execution ended."); break };
+            if outcome == StepOutcome::Halted {
+                let stats = &universe.stats;
+                println!("Execution ended ({} micro steps, {} rewinds).", stats.micro_steps, stats.rewinds);
+                println!("{}.", universe.power);
+                break
+            };
+
+            let (draw, brown_out, energy) = power::readings(&machine);
+            println!("Power: {} kW, {} J{}", draw, energy, if brown_out { " (brown-out)" } else { "" });
 
             println!("Display:");
-            let words = &machine.ram.0[0x14..=0x1a];
+            let words = machine.ram.read(0x14..0x1b);
             for d in 0usize..4 {
                 // println!("{}", d);
                 let mask = (1 << d) as u8;
@@ -88,5 +209,76 @@ fn main() -> std::io::Result<()> {
         }
     };
 
+    if let (Some(locations), true) = (inspected, status != 0) { inspect(&universe, locations) };
+
+    if let Err(err) = io_modules.log.finish() {
+        eprintln!("Could not save record file: {}", err);
+        status = 1;
+    }
+    if status != 0 { std::process::exit(status) };
     Ok(())
 }
+
+/// Prints the changes to `locations` over the states kept, then all that
+/// changed over them
+fn inspect(universe: &Universe, locations: Vec<Op>) {
+    let view = universe.view();
+    let range = view.range();
+    println!("Inspecting t={}..{} (present t={}):", range.start, range.end, view.present());
+    for op in locations {
+        match (view.first_change(op.clone()), view.last_change(op.clone())) {
+            (Some(first), Some(last)) => println!("{}: first changed at t={}, last at t={}", op, first, last),
+            _ => println!("{}: unchanged", op),
+        }
+        for (t, value) in view.changes(op) {
+            println!("  t={}: {:02x}", t, value.value())
+        }
+    }
+    let last = range.end - 1;
+    match view.diff(range.start, last) {
+        Some(diff) if !diff.is_empty() => print!("Changed over t={}..={}:\n{}", range.start, last, diff),
+        _ => println!("Nothing changed."),
+    }
+}
+
+fn bench(mut universe: Universe) {
+    let mut modules = ModuleCollection::new(vec![]);
+    let start = std::time::Instant::now();
+    let mut steps = 0;
+    loop {
+        match interpreter::step(&mut universe, &mut modules) {
+            Ok((_, StepOutcome::Halted)) => break,
+            Ok(_) => steps += 1,
+            Err(err) => { eprintln!("Error: {}.", err); break }
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let micro_steps = universe.stats.micro_steps;
+    eprintln!("{} steps ({} micro steps) in {:.2} s: {:.0} micro steps/s.", 
+        steps, micro_steps, elapsed, micro_steps as f64 / elapsed);
+    // Peak resident memory, where Linux reports it
+    let peak = std::fs::read_to_string("/proc/self/status").ok()
+        .and_then(|x| x.lines().find_map(|l| l.strip_prefix("VmHWM:")).map(|x| x.trim().to_string()));
+    eprintln!("Peak memory: {}.", peak.as_deref().unwrap_or("unknown"));
+}
+
+fn disassemble(dump: &str, start: usize) {
+    let words = dump.split_whitespace().enumerate().map(|(i, word)| {
+        let is_binary = word.len() == 6 && word.chars().all(|c| c == '0' || c == '1');
+        let radix = if is_binary { 2 } else { 16 };
+        u8::from_str_radix(word, radix).ok()
+            .and_then(|x| uWord::try_from(x).ok())
+            .unwrap_or_else(|| panic!("Invalid word {} at {:x}.", word, i))
+    }).collect::<Vec<_>>();
+    let ram = Ram::from(words);
+    let end = ram.end();
+    let listing = assembler::disassemble(&ram, start..end.max(start));
+    for (i, (address, _, mnemonic)) in listing.iter().enumerate() {
+        let next = listing.get(i+1).map_or(end, |x| usize::from(x.0));
+        let words = (usize::from(*address)..next)
+            .map(|j| format!("{:02x}", ram[j].value()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("{:<24}; {:02x}{:02x}: {}", mnemonic, address.lo().value(), address.hi().value(), words);
+    }
+}
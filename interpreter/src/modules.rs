@@ -4,22 +4,229 @@ use super::prelude::*;
 
 use std::error::Error;
 
+/// A device on the bus. It maps a range of addresses in the IO window, and is told
+/// of every read or write to them, with the time of the state accessed.
 pub trait Module: std::fmt::Debug {
-    /// Update the state.
-    fn run(&mut self, m: &mut Machine) -> Result<(), Box<dyn Error>>;
+    /// The addresses mapped, which must be in [`IO_WINDOW`]
+    fn range(&self) -> std::ops::Range<usize>;
+
+    /// Update the state at time `t`, before the CPU runs. This is where the
+    /// outside world comes in, and it is recorded and replayed (see [`IoLog`]).
+    fn tick(&mut self, _t: usize, _m: &mut Machine) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    /// `address` was read from `m`, the state at time `t`. Returns the words
+    /// to write to memory in response, like [`Module::on_write`]: as that 
+    /// state is in the past, the read sees them once the timeline is run 
+    /// again from there.
+    fn on_read(&mut self, _t: usize, _address: Address, _m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn Error>> {
+        Ok(vec![])
+    }
+
+    /// `value` was written to `address` in `m`, the state at time `t`. 
+    /// Returns the words to write to memory in response, which land in that
+    /// state (making the timeline inconsistent if it is in the past), and are
+    /// recorded and replayed like ticks.
+    fn on_write(&mut self, _t: usize, _address: Address, _value: uWord, _m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn Error>> {
+        Ok(vec![])
+    }
 }
 
-pub struct ModuleCollection(Vec<Box<dyn Module>>);
+pub struct ModuleCollection {
+    modules: Vec<Box<dyn Module>>,
+    pub log: IoLog,
+}
 
 impl ModuleCollection {
+    /// Panics if the modules map addresses outside the IO window, or the same
+    /// address twice (the power readings count as mapped)
     pub fn new(modules: Vec<Box<dyn Module>>) -> Self {
-        Self(modules)
+        let mut mapped = [false; IO_WINDOW.end];
+        for i in crate::power::POWER_IO { mapped[i] = true };
+        for module in &modules {
+            let range = module.range();
+            assert!(IO_WINDOW.start <= range.start && range.end <= IO_WINDOW.end, 
+                "{:?} maps {:x?}, outside the IO window", module, range);
+            for i in range {
+                assert!(!mapped[i], "{:?} maps {:x}, already mapped", module, i);
+                mapped[i] = true;
+            }
+        }
+        Self { modules, log: IoLog::default() }
     }
 
-    pub fn run(&mut self, universe: &mut Universe) {
-        for module in self.0.iter_mut() {
-            module.run(universe.now_mut()).expect("Failed to run IO module.");
+    /// Ticks the modules on the present state. The first time at each `t`, 
+    /// what they write to RAM is recorded; after that (when re-executing
+    /// after a rewind, or from a replay file), the recorded words are written
+    /// instead. Modules still run every time, to see the state.
+    pub fn run(&mut self, universe: &mut Universe) -> Result<(), Box<dyn Error>> {
+        let t = universe.t;
+        let before = universe.now().ram.clone();
+        for module in self.modules.iter_mut() {
+            module.tick(t, universe.now_mut())?;
+        }
+        let ram = &mut universe.now_mut().ram;
+        match self.log.effects.get(&t) {
+            Some(effects) => {
+                *ram = before;
+                for (address, value) in effects { ram[*address] = *value };
+            }
+            None if self.log.replaying => 
+                return Err(format!("replay ends before t={}", t).into()),
+            None => {
+                let effects = ram.changes(&before)
+                    .map(|i| (Address::try_from(i as u16).unwrap(), ram[i]))
+                    .collect();
+                self.log.effects.insert(t, effects);
+            }
         }
+        Ok(())
+    }
+
+    /// Hands the accesses to IO noted in `universe` to the modules mapping
+    /// them. Writes to the past or the future reach the module with the time
+    /// they land on. What the modules write in response is recorded for the
+    /// present, each time it runs (the CPU may not access the same after a 
+    /// rewind); when replaying, the recorded words are written instead.
+    pub fn dispatch(&mut self, universe: &mut Universe) -> Result<(), Box<dyn Error>> {
+        let mut writes = vec![];
+        for access in std::mem::take(&mut universe.bus) {
+            let address = usize::from(access.address());
+            let module = self.modules.iter_mut()
+                .find(|x| x.range().contains(&address));
+            let module = match module {
+                Some(module) => module,
+                None => continue,
+            };
+            match access {
+                BusAccess::Read { t, address } => {
+                    let words = module.on_read(t, address, &universe[t])?;
+                    writes.extend(words.into_iter().map(|(address, value)| (t, address, value)));
+                }
+                BusAccess::Write { t, address, value } => {
+                    let words = module.on_write(t, address, value, &universe[t])?;
+                    writes.extend(words.into_iter().map(|(address, value)| (t, address, value)));
+                }
+            }
+        }
+        let t = universe.t;
+        let writes = match self.log.replaying {
+            true => self.log.writes.get(&t).cloned().unwrap_or_default(),
+            false if writes.is_empty() => { self.log.writes.remove(&t); writes }
+            false => { self.log.writes.insert(t, writes.clone()); writes }
+        };
+        for (t, address, value) in writes {
+            crate::interpreter::write_io(universe, t, address, value).map_err(|x| x.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// The words written by IO modules at each time, so that a time that is
+/// executed again sees the same IO, and so that a whole run can be saved and
+/// replayed. 
+/// 
+/// The replay file has a line for each time, with `t` followed by the words
+/// written then, as `%llhh=vv`, and by the words written by modules on an
+/// access in that step, as `%llhh@-n=vv` for the state `n` before. Lines
+/// starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct IoLog {
+    effects: std::collections::BTreeMap<usize, Vec<(Address, uWord)>>,
+    /// Words written by modules on an access in each step, with the time of
+    /// the state they land on
+    writes: std::collections::BTreeMap<usize, Vec<(usize, Address, uWord)>>,
+    /// Where to save effects, if recording
+    record: Option<std::io::BufWriter<std::fs::File>>,
+    /// Whether effects come from a replay file (and are not to be recorded)
+    replaying: bool,
+}
+
+impl IoLog {
+    /// Records to a new file at `path`
+    pub fn record<P: AsRef<std::path::Path>>(mut self, path: P) -> std::io::Result<Self> {
+        use std::io::Write;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "# t %llhh=vv ...")?;
+        self.record = Some(file);
+        Ok(self)
+    }
+
+    /// Replays the file at `path`
+    pub fn replay<P: AsRef<std::path::Path>>(mut self, path: P) -> std::io::Result<Self> {
+        use std::io::BufRead;
+        let invalid = |line: &str| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, format!("invalid replay line: {}", line));
+        let f = std::io::BufReader::new(std::fs::File::open(path)?);
+        for line in f.lines() {
+            let line = line?;
+            if line.starts_with('#') || line.trim().is_empty() { continue };
+            let mut tokens = line.split_whitespace();
+            let t: usize = tokens.next().and_then(|x| x.parse().ok()).ok_or_else(|| invalid(&line))?;
+            let words = tokens.map(|token| {
+                let (address, value) = token.strip_prefix('%')?.split_once('=')?;
+                // Words written on an access carry the time of the state
+                let (address, t) = match address.split_once('@') {
+                    Some((address, offset)) => (address, Some(t.checked_add_signed(offset.parse().ok()?)?)),
+                    None => (address, None),
+                };
+                let address = crate::assembler::parse_address(address)?.ok()?;
+                let value = u8::from_str_radix(value, 16).ok()?.try_into().ok()?;
+                Some((t, address, value))
+            }).collect::<Option<Vec<_>>>().ok_or_else(|| invalid(&line))?;
+            let (writes, effects): (Vec<_>, Vec<_>) = words.into_iter().partition(|x| x.0.is_some());
+            self.effects.insert(t, effects.into_iter().map(|(_, address, value)| (address, value)).collect());
+            if !writes.is_empty() {
+                self.writes.insert(t, writes.into_iter().map(|(t, address, value)| (t.unwrap(), address, value)).collect());
+            }
+        }
+        self.replaying = true;
+        Ok(self)
+    }
+
+    /// Times before `t` will not be executed again: saves them, if recording,
+    /// and forgets them
+    pub fn forget(&mut self, t: usize) -> std::io::Result<()> {
+        let kept = self.effects.split_off(&t);
+        let done = std::mem::replace(&mut self.effects, kept);
+        let kept = self.writes.split_off(&t);
+        let writes = std::mem::replace(&mut self.writes, kept);
+        self.save(done, writes)
+    }
+
+    /// Saves everything left, if recording
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        use std::io::Write;
+        let done = std::mem::take(&mut self.effects);
+        let writes = std::mem::take(&mut self.writes);
+        self.save(done, writes)?;
+        if let Some(file) = &mut self.record { file.flush()? };
+        Ok(())
+    }
+
+    fn save(
+        &mut self, 
+        effects: std::collections::BTreeMap<usize, Vec<(Address, uWord)>>, 
+        mut writes: std::collections::BTreeMap<usize, Vec<(usize, Address, uWord)>>,
+    ) -> std::io::Result<()> {
+        use std::io::Write;
+        let file = match &mut self.record {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        // Every step ticks, so every time with writes has effects too
+        for (t, effects) in effects {
+            write!(file, "{}", t)?;
+            for (address, value) in effects {
+                write!(file, " {}={:02x}", Op::Abs(address), value.value())?;
+            }
+            for (t2, address, value) in writes.remove(&t).unwrap_or_default() {
+                write!(file, " {}@-{}={:02x}", Op::Abs(address), t - t2, value.value())?;
+            }
+            writeln!(file)?;
+        }
+        Ok(())
     }
 }
 
@@ -29,22 +236,25 @@ impl ModuleCollection {
 pub struct ClockModule;
 
 impl Module for ClockModule {
-    fn run(&mut self, m: &mut Machine) -> Result<(), Box<dyn Error>> {
+    fn range(&self) -> std::ops::Range<usize> {
+        0x10..0x14
+    }
+
+    fn tick(&mut self, _t: usize, m: &mut Machine) -> Result<(), Box<dyn Error>> {
         use chrono::Timelike;
         let now = chrono::Local::now();
         let hour = format!("{:0>2}", now.hour());
         let minute = format!("{:0>2}", now.minute());
-        let digits = hour.chars().chain(minute.chars());
-        m.ram[0x10..0x14]
-            .iter_mut()
-            .zip(digits)
-            .for_each(|(p, s)| *p = uWord::try_from(s.to_digit(10).unwrap() as u8).unwrap());
+        let digits = hour.chars().chain(minute.chars())
+            .map(|s| uWord::try_from(s.to_digit(10).unwrap() as u8).unwrap())
+            .collect::<Vec<_>>();
+        m.ram.write(0x10, &digits);
         Ok(())
     }
 }
 
 /// A module that reads a 4-digit seven-segment display from memory (see 
-/// manual for the format).
+/// manual for the format), whenever it is written.
 #[derive(Debug)]
 pub struct DisplayModule {
     pub hours: String,
@@ -85,8 +295,12 @@ impl DisplayModule {
 }
 
 impl Module for DisplayModule {
-    fn run(&mut self, m: &mut Machine) -> Result<(), Box<dyn Error>> {
-        let memory = &m.ram[0x14..0x14+7];
+    fn range(&self) -> std::ops::Range<usize> {
+        0x14..0x14+7
+    }
+
+    fn on_write(&mut self, _t: usize, _address: Address, _value: uWord, m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn Error>> {
+        let memory = m.ram.read(0x14..0x14+7);
 
         let a = memory[0..7]
             .iter()
@@ -122,13 +336,13 @@ impl Module for DisplayModule {
             d.encode_utf8(&mut bytes[1..2]);
         }
 
-        Ok(())
+        Ok(vec![])
     }
 }
 
 /// A module that emulates a "disk drive", i.e. maps an external file. Page `n` 
 /// (1k word pages) is requested by writing `n-1` to %3000,%3100 (big endian), 
-/// and served on addresses %0030–%3f3f.
+/// and served on addresses %0030–%3f3f when the request is written.
 #[derive(Debug)]
 pub struct DiskModule (Vec<uWord>);
 
@@ -148,27 +362,223 @@ impl DiskModule {
 }
 
 impl Module for DiskModule {
-    fn run(&mut self, m: &mut Machine) -> Result<(), Box<dyn Error>> {
+    fn range(&self) -> std::ops::Range<usize> {
+        0x30..0x32
+    }
+
+    fn on_write(&mut self, _t: usize, _address: Address, _value: uWord, m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn Error>> {
         let page = uLong::from_hi_lo(m.ram[0x31], m.ram[0x30]);
-        if page.value() != 0 {
-            let page = (page.value() - 1) as usize;
-            let start = page * 1024;
-            let end = start + 1024;
-            if start >= self.0.len() { 
-                ()
-            } else if end >= self.0.len() {
-                let len = self.0.len() - start;
-                let src = &self.0[start..start+len];
-                let dst = &mut m.ram[0x30*0x40 .. 0x30*0x40+len];
-                dst.clone_from_slice(src)
-                /*let dst = &mut m.ram[0x30*0x40+len .. 0x40*0x40];
-                dst.fill(uWord::ZERO);*/
-            } else {
-                let src = &self.0[start..end];
-                let dst = &mut m.ram[0x30*0x40 .. 0x40*0x40];
-                dst.clone_from_slice(src)
+        let words = match page.value() {
+            0 => &[][..],
+            page => {
+                let start = (page - 1) as usize * 1024;
+                let end = start + 1024;
+                if start >= self.0.len() { 
+                    &[][..]
+                } else if end >= self.0.len() {
+                    &self.0[start..]
+                } else {
+                    &self.0[start..end]
+                }
             }
+        };
+        let served = |i: usize| Address::try_from((0x30*0x40 + i) as u16).unwrap();
+        Ok(words.iter().enumerate().map(|(i, x)| (served(i), *x)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_into;
+    use crate::interpreter::{step, StepOutcome};
+    use crate::universe::MAX_JUMP;
+
+    /// Notes every access it is told of
+    #[derive(Debug, Default)]
+    struct Probe(std::rc::Rc<std::cell::RefCell<Vec<BusAccess>>>);
+
+    impl Module for Probe {
+        fn range(&self) -> std::ops::Range<usize> {
+            0x20..0x22
         }
-        Ok(())
+
+        fn on_read(&mut self, t: usize, address: Address, _m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn std::error::Error>> {
+            self.0.borrow_mut().push(BusAccess::Read { t, address });
+            Ok(vec![])
+        }
+
+        fn on_write(&mut self, t: usize, address: Address, value: uWord, m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn std::error::Error>> {
+            // The write is already in the state it is handed
+            assert_eq!(m.ram[address], value);
+            self.0.borrow_mut().push(BusAccess::Write { t, address, value });
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn bus() {
+        let source = "mov #05 %2000\nmov %2000 a\nmov #07 %2100@+2\nmov a %0001\nnop\nnop\nhcf";
+        let probe = Probe::default();
+        let accesses = probe.0.clone();
+        let mut modules = ModuleCollection::new(vec![Box::new(probe)]);
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        while step(&mut universe, &mut modules).unwrap().1 != StepOutcome::Halted {}
+        let address = |x: u16| Address::try_from(x).unwrap();
+        assert_eq!(*accesses.borrow(), vec![
+            BusAccess::Write { t: 1, address: address(0x20), value: uWord::lit(0x05) },
+            BusAccess::Read { t: 1, address: address(0x20) },
+            // Arrives at the time it was sent to
+            BusAccess::Write { t: 5, address: address(0x21), value: uWord::lit(0x07) },
+        ]);
+    }
+
+    /// Supplies its value on every read of %2200
+    #[derive(Debug)]
+    struct Supplier(u8);
+
+    impl Module for Supplier {
+        fn range(&self) -> std::ops::Range<usize> {
+            0x22..0x23
+        }
+
+        fn on_read(&mut self, _t: usize, address: Address, _m: &Machine) -> Result<Vec<(Address, uWord)>, Box<dyn std::error::Error>> {
+            Ok(vec![(address, uWord::lit(self.0))])
+        }
+    }
+
+    #[test]
+    fn supplied_on_read() {
+        let source = "mov %2200 a\nnop\nnop\nnop\nnop\nnop\nhcf";
+        let path = std::env::temp_dir().join(format!("tau800-read-replay-{}", std::process::id()));
+        let run = |modules: &mut ModuleCollection| {
+            let mut universe = Universe::new(UniverseConfig::default());
+            assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+            while step(&mut universe, modules).unwrap().1 != StepOutcome::Halted {}
+            (universe.now().cpu.a, universe.stats.rewinds)
+        };
+        let mut modules = ModuleCollection::new(vec![Box::new(Supplier(0x2a))]);
+        modules.log = std::mem::take(&mut modules.log).record(&path).unwrap();
+        // The read sees the value once the timeline is run again
+        assert_eq!(run(&mut modules), (uWord::lit(0x2a), 1));
+        modules.log.finish().unwrap();
+        let mut modules = ModuleCollection::new(vec![Box::new(Supplier(0x15))]);
+        modules.log = std::mem::take(&mut modules.log).replay(&path).unwrap();
+        let replayed = run(&mut modules);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, (uWord::lit(0x2a), 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Stray maps 6f..71, outside the IO window")]
+    fn bus_outside_window() {
+        #[derive(Debug)]
+        struct Stray;
+        impl Module for Stray {
+            fn range(&self) -> std::ops::Range<usize> { 0x6f..0x71 }
+        }
+        ModuleCollection::new(vec![Box::new(Stray)]);
+    }
+
+    /// Writes how many times it ran to %1000
+    #[derive(Debug)]
+    struct Counter(u8);
+
+    impl Module for Counter {
+        fn range(&self) -> std::ops::Range<usize> {
+            0x10..0x11
+        }
+
+        fn tick(&mut self, _t: usize, m: &mut Machine) -> Result<(), Box<dyn std::error::Error>> {
+            self.0 += 1;
+            m.ram[0x10] = uWord::try_from(self.0 & 0x3f).unwrap();
+            Ok(())
+        }
+    }
+
+    /// Steps `source` until it halts, and returns the value %1000 had at each time
+    fn run_io(source: &str, modules: &mut ModuleCollection) -> Vec<u8> {
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        let mut values = vec![];
+        loop {
+            let (machine, outcome) = step(&mut universe, modules).unwrap();
+            values.push(machine.ram[0x10].value());
+            if outcome == StepOutcome::Halted { return values }
+        }
+    }
+
+    #[test]
+    fn io_replayed_after_rewind() {
+        // Rewinds once, from t=4 to t=0
+        let source = "mov %0001@+3 a\nmov #04 %0001\nnop\nnop\nhcf";
+        let mut modules = ModuleCollection::new(vec![Box::new(Counter(0))]);
+        let values = run_io(source, &mut modules);
+        // Each time keeps the value from its first run (the last state popped
+        // is the final one, further on)
+        let n = values.len() - 1;
+        assert_eq!(values[..n], (0..n as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn io_record_replay() {
+        let source = "mov %0001@+3 a\nmov #04 %0001\nnop\nnop\nhcf";
+        let path = std::env::temp_dir().join(format!("tau800-replay-{}", std::process::id()));
+        let mut modules = ModuleCollection::new(vec![Box::new(Counter(0))]);
+        modules.log = std::mem::take(&mut modules.log).record(&path).unwrap();
+        let recorded = run_io(source, &mut modules);
+        modules.log.finish().unwrap();
+        // A different outside world does not matter
+        let mut modules = ModuleCollection::new(vec![Box::new(Counter(30))]);
+        modules.log = std::mem::take(&mut modules.log).replay(&path).unwrap();
+        let replayed = run_io(source, &mut modules);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recorded, replayed);
+    }
+
+    /// A disk module over a file holding `words`
+    fn disk(words: &str) -> DiskModule {
+        let path = std::env::temp_dir().join(format!("tau800-disk-{}-{}", std::process::id(), words.len()));
+        std::fs::write(&path, words).unwrap();
+        let disk = DiskModule::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        disk
+    }
+
+    /// Steps `source` until it halts
+    fn run_disk(source: &str, modules: &mut ModuleCollection) -> Universe {
+        let mut universe = Universe::new(UniverseConfig::default());
+        assemble_into(universe.now_mut(), source, MAX_JUMP).unwrap();
+        while step(&mut universe, modules).unwrap().1 != StepOutcome::Halted {}
+        universe
+    }
+
+    #[test]
+    fn disk_record_replay() {
+        // Requests page 1, which is served from %0030
+        let source = "mov #01 %3000\nnop\nhcf";
+        let path = std::env::temp_dir().join(format!("tau800-disk-replay-{}", std::process::id()));
+        let mut modules = ModuleCollection::new(vec![Box::new(disk("01 02 03"))]);
+        modules.log = std::mem::take(&mut modules.log).record(&path).unwrap();
+        let recorded = run_disk(source, &mut modules).now().ram.read(0xc00..0xc04);
+        modules.log.finish().unwrap();
+        assert_eq!(recorded, [0x01, 0x02, 0x03, 0x00].map(uWord::lit));
+        // What the disk holds now does not matter
+        let mut modules = ModuleCollection::new(vec![Box::new(disk("3f 3f"))]);
+        modules.log = std::mem::take(&mut modules.log).replay(&path).unwrap();
+        let replayed = run_disk(source, &mut modules).now().ram.read(0xc00..0xc04);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recorded, replayed);
+    }
+
+    #[test]
+    fn disk_past() {
+        // The page loads in the state written to, which rewinds
+        let source = "nop\nnop\nmov #01 %3000@-2\nnop\nhcf";
+        let mut modules = ModuleCollection::new(vec![Box::new(disk("2a"))]);
+        let universe = run_disk(source, &mut modules);
+        assert_eq!(universe.now().ram[0xc00], uWord::lit(0x2a));
+        assert_eq!(universe.stats.rewinds, 1);
     }
 }
@@ -0,0 +1,164 @@
+//! Diagnostics for timelines that fail to become consistent
+
+use crate::prelude::*;
+
+/// A read or write that contradicts the timeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// A read from the future (of `op` at time `t`) guessed a value, but a
+    /// different one was there when that time came
+    Read { t: usize, op: Op, guessed: uWord, observed: uWord },
+    /// A write into the past (to `op` at time `t`) changed the value that had
+    /// been recorded there
+    Write { t: usize, op: Op, recorded: uWord, written: uWord },
+}
+
+/// Identifies a read or write across rewinds: (is read, time, operand)
+type Key<'a> = (bool, usize, &'a Op);
+
+impl Conflict {
+    fn key(&self) -> Key<'_> {
+        match self {
+            Conflict::Read { t, op, .. } => (true, *t, op),
+            Conflict::Write { t, op, .. } => (false, *t, op),
+        }
+    }
+
+    /// The value that won, and that the next iteration will start from
+    fn value(&self) -> uWord {
+        match self {
+            Conflict::Read { observed, .. } => *observed,
+            Conflict::Write { written, .. } => *written,
+        }
+    }
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::Read { t, op, guessed, observed } => write!(f,
+                "read of {} at t={} guessed {:02x}, found {:02x}",
+                op, t, guessed.value(), observed.value()),
+            Conflict::Write { t, op, recorded, written } => write!(f,
+                "write of {:02x} to {} at t={}, where {:02x} was recorded",
+                written.value(), op, t, recorded.value()),
+        }
+    }
+}
+
+/// One rewind: the window of the timeline that was re-run, and the conflicts
+/// that made it inconsistent
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    pub ti: usize,
+    pub tf: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A read or write whose value repeats with a period, across rewinds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cycle {
+    pub read: bool,
+    pub t: usize,
+    pub op: Op,
+    /// One period of the values, in order
+    pub values: Vec<uWord>,
+}
+
+impl std::fmt::Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.read { "read of" } else { "write to" };
+        write!(f, "{} {} at t={} cycles ", kind, self.op, self.t)?;
+        for x in &self.values {
+            write!(f, "{:02x} → ", x.value())?;
+        }
+        write!(f, "{:02x}", self.values[0].value())
+    }
+}
+
+/// Why a timeline could not be made consistent
+#[derive(Debug, Clone, Default)]
+pub struct ParadoxReport {
+    /// Every rewind since the timeline was last consistent, oldest first
+    pub rewinds: Vec<Rewind>,
+    /// Conflicts found since the last rewind
+    pub pending: Vec<Conflict>,
+}
+
+impl ParadoxReport {
+    /// Records a conflict, to be attached to the next rewind
+    pub fn add(&mut self, conflict: Conflict) {
+        self.pending.push(conflict)
+    }
+
+    /// Records a rewind of the window `ti..tf`
+    pub fn rewind(&mut self, ti: usize, tf: usize) {
+        let conflicts = std::mem::take(&mut self.pending);
+        self.rewinds.push(Rewind { ti, tf, conflicts })
+    }
+
+    pub fn clear(&mut self) {
+        self.rewinds.clear();
+        self.pending.clear();
+    }
+
+    /// The last conflict found
+    pub fn last(&self) -> Option<&Conflict> {
+        self.pending.last()
+            .or_else(|| self.rewinds.iter().rev().find_map(|x| x.conflicts.last()))
+    }
+
+    /// Reads and writes whose values settle into a cycle: the last period
+    /// repeats at least twice
+    pub fn cycles(&self) -> Vec<Cycle> {
+        // Few distinct operands conflict, so a linear search will do
+        let mut history: Vec<(Key, Vec<uWord>)> = vec![];
+        let conflicts = self.rewinds.iter().flat_map(|x| x.conflicts.iter()).chain(self.pending.iter());
+        for conflict in conflicts {
+            let key = conflict.key();
+            match history.iter_mut().find(|x| x.0 == key) {
+                Some((_, values)) => values.push(conflict.value()),
+                None => history.push((key, vec![conflict.value()])),
+            }
+        }
+        history.into_iter().filter_map(|(key, values)| {
+            let n = values.len();
+            let period = (1..=n/2).find(|&p| values[n-p..] == values[n-2*p..n-p])?;
+            let (read, t, op) = key;
+            Some(Cycle { read, t, op: op.clone(), values: values[n-period..].to_vec() })
+        }).collect()
+    }
+}
+
+/// Rewinds listed in full in the report; the rest are summarised
+const SHOWN_REWINDS: usize = 8;
+
+impl std::fmt::Display for ParadoxReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.rewinds.len();
+        writeln!(f, "{} rewinds without reaching a consistent timeline.", n)?;
+        if n > SHOWN_REWINDS {
+            writeln!(f, "  ({} earlier rewinds omitted)", n - SHOWN_REWINDS)?;
+        }
+        for (i, rewind) in self.rewinds.iter().enumerate().skip(n.saturating_sub(SHOWN_REWINDS)) {
+            writeln!(f, "  #{} rewound t={}..{}", i+1, rewind.ti, rewind.tf)?;
+            for conflict in &rewind.conflicts {
+                writeln!(f, "      {}", conflict)?;
+            }
+        }
+        if !self.pending.is_empty() {
+            writeln!(f, "  since the last rewind")?;
+            for conflict in &self.pending {
+                writeln!(f, "      {}", conflict)?;
+            }
+        }
+        let cycles = self.cycles();
+        if !cycles.is_empty() {
+            writeln!(f, "Oscillating values:")?;
+            for cycle in cycles {
+                writeln!(f, "  {}", cycle)?;
+            }
+        }
+        Ok(())
+    }
+}
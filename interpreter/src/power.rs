@@ -0,0 +1,227 @@
+//! Power drawn by the Novikov module for time jumps, and the secondary supply
+//! that feeds it
+
+use crate::prelude::*;
+use crate::instruction::Timed;
+use crate::timing::CLOCK_HZ;
+use std::borrow::Cow;
+
+/// Where the readings of each state are mapped: the draw of its step in kW,
+/// whether it browned out, and the energy drawn along the timeline up to it in
+/// J (lo, hi)
+pub const POWER_IO: std::ops::Range<usize> = 0x38..0x3c;
+
+/// Typical ratings of the Novikov module, as in the manual: W drawn for a jump
+/// of each offset
+const RATINGS: [(isize, u32); 9] = [
+    (-200, 12_900), (-150, 11_400), (-100, 6_500), (-50, 2_200), (0, 900),
+    (50, 11_200), (100, 22_300), (150, 26_900), (200, 29_100),
+];
+
+/// W drawn for a jump of `offset`, interpolated from the typical ratings (and
+/// flat past the last ones)
+pub fn rating(offset: isize) -> u32 {
+    let (first, last) = (RATINGS[0], RATINGS[RATINGS.len()-1]);
+    let offset = offset.clamp(first.0, last.0);
+    match RATINGS.iter().position(|x| x.0 >= offset).unwrap() {
+        0 => first.1,
+        i => {
+            let ((x0, y0), (x1, y1)) = (RATINGS[i-1], RATINGS[i]);
+            (y0 as isize + (y1 as isize - y0 as isize) * (offset - x0) / (x1 - x0)) as u32
+        }
+    }
+}
+
+/// A secondary power supply, which browns out on jumps farther than it can feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supply {
+    pub name: &'static str,
+    pub max_jump: usize,
+}
+
+/// The standard supply, for jumps of up to 80 cycles
+pub const SPS_3_5000: Supply = Supply { name: "SPS-3-5000", max_jump: 80 };
+/// The upgraded supply, for jumps of up to 200 cycles
+pub const SPS_3_6000: Supply = Supply { name: "SPS-3-6000", max_jump: 200 };
+
+/// What happens on a jump farther than the supply can feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnBrownOut {
+    /// The CPU faults, and the access does not happen
+    Fault,
+    /// The jump falls short, as far as the supply reaches
+    Degrade,
+}
+
+/// What one micro step drew
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Step {
+    /// W
+    pub draw: u32,
+    pub brown_out: bool,
+    /// mJ
+    pub energy: u64,
+}
+
+/// Power drawn by the Novikov module over a run, fed by every temporal operand
+/// and every rewind
+#[derive(Debug, Clone)]
+pub struct PowerModel {
+    /// None for mains, which never browns out
+    pub supply: Option<Supply>,
+    pub on_brown_out: OnBrownOut,
+    /// The micro step under way
+    pub step: Step,
+    /// Highest draw of a micro step, in W
+    pub peak: u32,
+    /// Energy drawn in all, including steps re-run and rewinds, in mJ
+    pub energy: u64,
+    pub brown_outs: usize,
+}
+
+impl Default for PowerModel {
+    fn default() -> Self {
+        PowerModel { supply: None, on_brown_out: OnBrownOut::Fault, step: Step::default(), peak: 0, energy: 0, brown_outs: 0 }
+    }
+}
+
+/// mJ drawn at `draw` W for `cycles` clock cycles
+fn energy(draw: u32, cycles: u64) -> u64 {
+    draw as u64 * cycles * 1000 / CLOCK_HZ
+}
+
+impl PowerModel {
+    /// Feeds the jump of `operand`. Returns the operand as it happens (a jump
+    /// may fall short), or a fault if the supply browned out.
+    pub fn jump<'a>(&mut self, operand: &'a Operand) -> Result<Cow<'a, Operand>, Fault> {
+        let offset = operand.time.value() as isize;
+        if offset == 0 { return Ok(Cow::Borrowed(operand)) };
+        let reach = self.supply.map_or(usize::MAX, |x| x.max_jump);
+        if offset.unsigned_abs() <= reach {
+            self.step.draw += rating(offset);
+            return Ok(Cow::Borrowed(operand))
+        }
+        self.brown_outs += 1;
+        self.step.brown_out = true;
+        match self.on_brown_out {
+            OnBrownOut::Fault => Err(Fault::BrownOut(offset)),
+            OnBrownOut::Degrade => {
+                let offset = offset.clamp(-(reach as isize), reach as isize);
+                self.step.draw += rating(offset);
+                let time = iLong::try_from(offset as i16).unwrap();
+                Ok(Cow::Owned(Timed { op: operand.op.clone(), time }))
+            }
+        }
+    }
+
+    /// Feeds a rewind over `length` states, which draws as a jump back as far,
+    /// for a cycle
+    pub fn rewind(&mut self, length: usize) {
+        self.energy += energy(rating(-(length as isize)), 1)
+    }
+
+    /// Ends the micro step under way, which took `cycles`, and returns what it
+    /// drew
+    pub fn finish(&mut self, cycles: u64) -> Step {
+        let mut step = std::mem::take(&mut self.step);
+        step.energy = energy(step.draw, cycles);
+        self.energy += step.energy;
+        self.peak = self.peak.max(step.draw);
+        step
+    }
+}
+
+/// Adds what `step` drew to `m`, and maps the readings into its memory
+pub fn record(m: &mut Machine, step: &Step) {
+    m.energy += step.energy;
+    let kw = uWord::try_from(((step.draw + 500) / 1000).min(63) as u8).unwrap();
+    let joules = uLong::try_from((m.energy / 1000).min(0xfff) as u16).unwrap();
+    let brown_out = uWord::try_from(step.brown_out as u8).unwrap();
+    let words = [kw, brown_out, joules.lo(), joules.hi()];
+    // Leave the page shared if nothing changed
+    if m.ram.read(POWER_IO) != words {
+        m.ram.write(POWER_IO.start, &words)
+    }
+}
+
+/// The readings mapped in `m`: draw in kW, whether it browned out, and energy
+/// along the timeline in J
+pub fn readings(m: &Machine) -> (u8, bool, u16) {
+    let words = m.ram.read(POWER_IO);
+    let joules = uLong::from_hi_lo(words[3], words[2]);
+    (words[0].value(), words[1].value() != 0, joules.value())
+}
+
+/// Parses a power supply, as given on the command line: `mains`, `sps-3-5000`
+/// or `sps-3-6000`, then optionally `:fault` or `:degrade` for what happens on
+/// a brown-out (default fault)
+pub fn parse_power(s: &str) -> Option<PowerModel> {
+    let (name, arg) = match s.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (s, None),
+    };
+    let supply = match name {
+        "mains" => None,
+        "sps-3-5000" => Some(SPS_3_5000),
+        "sps-3-6000" => Some(SPS_3_6000),
+        _ => return None,
+    };
+    let on_brown_out = match arg {
+        None | Some("fault") => OnBrownOut::Fault,
+        Some("degrade") => OnBrownOut::Degrade,
+        _ => return None,
+    };
+    Some(PowerModel { supply, on_brown_out, ..Default::default() })
+}
+
+impl std::fmt::Display for PowerModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let supply = self.supply.map_or("mains", |x| x.name);
+        write!(f, "Novikov module ({}): {:.1} J drawn, peak {:.1} kW, {} brown-outs",
+            supply, self.energy as f64 / 1000., self.peak as f64 / 1000., self.brown_outs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_into;
+    use crate::interpreter::{step_one, EmuError};
+    use crate::universe::MAX_JUMP;
+
+    #[test]
+    fn power() {
+        assert_eq!(rating(0), 900);
+        assert_eq!(rating(75), 16_750);
+        assert_eq!(rating(-1000), 12_900);
+        // Faults past the reach of the supply, without the access
+        let mut universe = Universe::new(UniverseConfig::default());
+        universe.power = parse_power("sps-3-5000").unwrap();
+        assemble_into(universe.now_mut(), "mov #05 %0001@+81\nhcf", MAX_JUMP).unwrap();
+        let mut modules = ModuleCollection::new(vec![]);
+        let result = step_one(&mut universe, &mut modules);
+        assert!(matches!(result, Err(EmuError::Fault(Fault::BrownOut(81)))));
+        assert!(!universe.pending_writes.any_after(0));
+        // A read that browns out ends the instruction, leaving dst and flags
+        let mut universe = Universe::new(UniverseConfig::default());
+        universe.power = parse_power("sps-3-5000").unwrap();
+        assemble_into(universe.now_mut(), "mov #05 a\nmov %0001@-81 a\nhcf", MAX_JUMP).unwrap();
+        step_one(&mut universe, &mut modules).unwrap();
+        let flags = universe.now().cpu.flags.word();
+        let result = step_one(&mut universe, &mut modules);
+        assert!(matches!(result, Err(EmuError::Fault(Fault::BrownOut(-81)))));
+        assert_eq!((universe.now().cpu.a, universe.now().cpu.flags.word()), (uWord::lit(0x05), flags));
+        // Or falls short
+        let mut universe = Universe::new(UniverseConfig::default());
+        universe.power = parse_power("sps-3-5000:degrade").unwrap();
+        assemble_into(universe.now_mut(), "mov #05 %0001@+100\nmov #05 %0001@+50\nhcf", MAX_JUMP).unwrap();
+        step_one(&mut universe, &mut modules).unwrap();
+        assert_eq!(universe.pending_writes.at(universe.t + 80).len(), 1);
+        assert_eq!(readings(universe.now()), (18, true, 0));
+        step_one(&mut universe, &mut modules).unwrap();
+        assert!(!readings(universe.now()).1);
+        assert_eq!(universe.power.brown_outs, 1);
+        assert_eq!(universe.power.peak, 17_860);
+        assert_eq!(universe.now().energy, universe.power.energy);
+    }
+}
@@ -2,8 +2,8 @@ pub(super) use more_asserts::*;
 
 pub(super) use crate::word::*;
 pub(super) use crate::machine::*;
-pub(super) use crate::instruction::{Instruction, Op, Operand, Operands, Register};
-pub(super) use crate::universe::{Universe, Mode};
+pub(super) use crate::instruction::{DecodeError, Instruction, Op, Operand, Operands, Pair, Register};
+pub(super) use crate::universe::{Universe, UniverseConfig, Mode};
 pub(super) use crate::modules::ModuleCollection;
 
 // Debug macros (como é que isto não vem standard)
@@ -0,0 +1,93 @@
+//! Clock cycles taken by instructions, and running at the speed of the clock
+
+use crate::prelude::*;
+use std::time::{Duration, Instant};
+
+/// Nominal clock speed, as in the manual (0.66 MHz)
+pub const CLOCK_HZ: u64 = 660_000;
+
+/// What a time offset (`@±n`), and one slot of the timeline, counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeUnit {
+    /// One instruction per slot, whatever its cost
+    #[default]
+    Instructions,
+    /// One clock cycle per slot: an instruction takes as many slots as it
+    /// costs, with its effects visible from the first one
+    Cycles,
+}
+
+/// Parses a time unit, as given on the command line: `instructions` or 
+/// `cycles`
+pub fn parse_unit(s: &str) -> Option<TimeUnit> {
+    match s {
+        "instructions" => Some(TimeUnit::Instructions),
+        "cycles" => Some(TimeUnit::Cycles),
+        _ => None,
+    }
+}
+
+/// Cycles to fetch and access an operand, on top of those of the opcode
+fn operand_cycles(operand: &Operand) -> u8 {
+    let access = match operand.op {
+        Op::Reg(_) => 0,
+        Op::Imm(_) => 1,
+        // Two words of address, then the access
+        Op::Abs(_) => 3,
+        Op::Abx(_) => 4,
+        // Two words of address, two of pointer, then the access
+        Op::Ind(_) => 5,
+        Op::Inr(_) => 1,
+    };
+    // Two words of time offset, and a round trip on the causal-consistency bus
+    let temporal = if operand.time.value() != 0 { 4 } else { 0 };
+    access + temporal
+}
+
+/// Clock cycles taken by `instruction`
+pub fn cycles(instruction: &Instruction) -> u8 {
+    use Instruction::*;
+    // Fetching and decoding the opcode
+    const BASE: u8 = 2;
+    let two = |x: &Operands| operand_cycles(&x.src) + operand_cycles(&x.dst);
+    match instruction {
+        Mov(x) | Add(x) | Sub(x) | And(x) | Or(x) | Xor(x) | Cmp(x) | Bit(x) => BASE + two(x),
+        // Writes back both operands
+        Xch(x) => BASE + 1 + two(x),
+        Mul(x) | Muh(x) | Mus(x) => BASE + 4 + two(x),
+        Div(x) | Dis(x) | Mod(x) | Mos(x) => BASE + 8 + two(x),
+        // One access to the stack
+        Psh(x) | Pop(x) => BASE + 1 + operand_cycles(x),
+        Not(x) | Lsl(x) | Lsr(x) | Asr(x) | Inc(x) | Dec(x) => BASE + operand_cycles(x),
+        // Two words of address
+        Jmp(_) => BASE + 2,
+        // One word of offset
+        Bcc(_) | Bcs(_) | Bne(_) | Beq(_) | Bpl(_) | Bmi(_) => BASE + 1,
+        // Two words of address, and two accesses to the stack
+        Cal(_) => BASE + 4,
+        Ret => BASE + 2,
+        Clc | Sec | Nop | Hcf => BASE,
+    }
+}
+
+/// Keeps the emulator from running faster than the clock
+pub struct Throttle {
+    start: Instant,
+    /// Cycle count when started
+    cycles: u64,
+}
+
+impl Throttle {
+    pub fn new(cycles: u64) -> Self {
+        Throttle { start: Instant::now(), cycles }
+    }
+
+    /// Sleeps until the clock would have taken this long to reach `cycles`
+    pub fn wait(&self, cycles: u64) {
+        let elapsed = cycles.saturating_sub(self.cycles);
+        let target = Duration::from_nanos(elapsed * 1_000_000_000 / CLOCK_HZ);
+        if let Some(left) = target.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(left)
+        }
+    }
+}
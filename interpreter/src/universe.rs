@@ -1,13 +1,86 @@
 use crate::prelude::*;
-use std::collections::VecDeque;
+use crate::interpreter::{ConsistencyStrategy, EmuError, Naive};
+use crate::inspect::TimelineView;
+use crate::paradox::ParadoxReport;
+use crate::power::PowerModel;
+use crate::timing::TimeUnit;
+use std::collections::{BTreeMap, VecDeque};
 
-const MAX_WINDOW: usize = 4 * (iLong::MAX.value() as usize);
+/// Farthest time jump the encoding reaches (`@-2048`)
+pub const MAX_JUMP: usize = iLong::MIN.value().unsigned_abs() as usize;
+
+/// Limits of the time machine. Times are in slots of the timeline (see 
+/// [`TimeUnit`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniverseConfig {
+    /// Farthest time jump (`@±n`) allowed. The manual allows 200 cycles, or 
+    /// 80 without the upgraded power supply; the default is as far as the 
+    /// encoding reaches.
+    pub max_jump: usize,
+    /// States kept behind the present, after which the oldest is final (up 
+    /// to twice as many are kept, to rewind into)
+    pub window: usize,
+    /// States re-run past a write into the past, after the present
+    pub rewind_padding: usize,
+    /// Micro steps spent on an inconsistent window before giving up with a
+    /// paradox
+    pub iteration_limit: usize,
+}
+
+impl Default for UniverseConfig {
+    fn default() -> Self {
+        UniverseConfig {
+            max_jump: MAX_JUMP,
+            window: 4 * (iLong::MAX.value() as usize),
+            rewind_padding: 4,
+            iteration_limit: 1000*10,
+        }
+    }
+}
+
+impl UniverseConfig {
+    /// Parses a config, as given on the command line: comma-separated 
+    /// `key=value` (keys `jump`, `window`, `padding` and `iterations`), over
+    /// the defaults. A window left out follows the jump.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut config = UniverseConfig::default();
+        let mut window = None;
+        for item in s.split(',').filter(|x| !x.is_empty()) {
+            let (key, value) = item.split_once('=')?;
+            let value = value.parse().ok()?;
+            match key {
+                "jump" => config.max_jump = value,
+                "window" => window = Some(value),
+                "padding" => config.rewind_padding = value,
+                "iterations" => config.iteration_limit = value,
+                _ => return None,
+            }
+        }
+        config.window = window.unwrap_or(4*config.max_jump);
+        config.check().ok()?;
+        Some(config)
+    }
+
+    /// Fails if a jump could land outside the states kept, or past the 
+    /// encoding
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_jump > MAX_JUMP {
+            return Err(format!("jumps are at most {}", MAX_JUMP))
+        }
+        if self.window <= self.max_jump + self.rewind_padding {
+            return Err("window too short for the jump".to_string())
+        }
+        Ok(())
+    }
+}
 
 /// Represents a timeline slice starting at time t0
 #[derive(Debug, Clone)]
 pub struct Timeline {
     t0: usize, 
     states: VecDeque<Machine>, 
+    /// See [`UniverseConfig::window`]
+    window: usize,
 }
 
 impl std::ops::Add<iLong> for usize {
@@ -22,12 +95,14 @@ impl std::ops::Add<iLong> for usize {
 impl std::ops::Index<usize> for Timeline {
     type Output = Machine;
     fn index(&self, t: usize) -> &Self::Output {
+        assert!(self.in_interval(t), "t={} is not in the timeline ({}..{})", t, self.ti(), self.tf());
         &self.states[t - self.t0]
     }
 }
 
 impl std::ops::IndexMut<usize> for Timeline {
     fn index_mut(&mut self, t: usize) -> &mut Self::Output {
+        assert!(self.in_interval(t), "t={} is not in the timeline ({}..{})", t, self.ti(), self.tf());
         &mut self.states[t - self.t0]
     }
 }
@@ -68,7 +143,7 @@ impl Timeline {
     }
 
     pub fn push_back(&mut self, x: Machine) {
-        if self.states.len() == 2*MAX_WINDOW {
+        if self.states.len() == 2*self.window {
             self.pop_front();
         };
         self.states.push_back(x);
@@ -80,7 +155,7 @@ impl Timeline {
     }
 
     pub fn is_full(&self) -> bool {
-        self.states.len() >= MAX_WINDOW
+        self.states.len() >= self.window
     }
 }
 
@@ -110,30 +185,115 @@ impl Mode {
     }
 }
 
+/// Reads from or writes to a time in the future, waiting for that time, and
+/// kept until the timeline moves past it (a rewind may run it again). Each is
+/// noted with the time of the instruction that issued it.
+#[derive(Debug, Clone)]
+pub struct Pending<T>(BTreeMap<usize, Vec<(usize, T)>>);
+
+impl<T> Default for Pending<T> {
+    fn default() -> Self {
+        Pending(BTreeMap::new())
+    }
+}
+
+impl<T> Pending<T> {
+    /// Adds `x`, issued at time `issued`, for time `t`
+    pub fn push(&mut self, t: usize, issued: usize, x: T) {
+        self.0.entry(t).or_default().push((issued, x))
+    }
+
+    /// Everything for time `t`
+    pub fn at(&self, t: usize) -> &[(usize, T)] {
+        self.0.get(&t).map_or(&[], |x| x.as_slice())
+    }
+
+    /// Whether anything is for a time after `t`
+    pub fn any_after(&self, t: usize) -> bool {
+        self.0.range(t+1..).next().is_some()
+    }
+
+    /// Drops everything for times before `t`
+    pub fn forget(&mut self, t: usize) {
+        while let Some(entry) = self.0.first_entry() {
+            if *entry.key() >= t { break }
+            entry.remove();
+        }
+    }
+
+    /// Drops everything issued after `t`: the instructions that issued it 
+    /// run again after a rewind to `t`, and issue it anew
+    pub fn rewind(&mut self, t: usize) {
+        let mut empty = vec![];
+        for (key, xs) in self.0.range_mut(t+1..) {
+            xs.retain(|x| x.0 <= t);
+            if xs.is_empty() { empty.push(*key) };
+        }
+        for key in empty { self.0.remove(&key); }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &(usize, T))> {
+        self.0.iter().flat_map(|(t, xs)| xs.iter().map(move |x| (*t, x)))
+    }
+}
+
+/// Counters over a run
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Micro steps taken, including those re-run to resolve inconsistencies
+    pub micro_steps: usize,
+    /// Times the timeline was rewound to resolve an inconsistency
+    pub rewinds: usize,
+}
+
 pub struct Universe {
     pub timeline: Timeline, 
     pub t: usize,
     pub mode: Mode, 
-    pub pending_writes: Vec<(usize, Op, uWord)>,  // Janky
-    pub pending_reads: Vec<(usize, usize, Op, uWord)>,
+    /// Writes to the future, of a value to an operand
+    pub pending_writes: Pending<(Op, uWord)>,
+    /// Reads from the future, of an operand and the value guessed for it
+    pub pending_reads: Pending<(Op, uWord)>,
+    /// Rewinds and conflicts since the timeline was last consistent
+    pub paradox: ParadoxReport,
+    /// Accesses to memory-mapped IO in this micro step, for the modules
+    pub bus: Vec<BusAccess>,
+    /// How values of reads from the future are guessed
+    pub strategy: Box<dyn ConsistencyStrategy>,
+    /// Whether a slot of the timeline is an instruction or a clock cycle
+    pub unit: TimeUnit,
+    /// Power drawn by the time jumps, and the supply feeding them
+    pub power: PowerModel,
+    pub stats: Stats,
+    pub config: UniverseConfig,
 }
 
 impl Universe {
-    pub fn new() -> Self {
+    /// Panics if `config` does not pass [`UniverseConfig::check`]
+    pub fn new(config: UniverseConfig) -> Self {
+        if let Err(err) = config.check() { panic!("Invalid universe config: {}.", err) };
         Universe {
             timeline: Timeline {
                 states: VecDeque::from(vec![Machine::new()]),
                 t0: 0,
+                window: config.window,
             },
             t: 0,
             mode: Mode::Consistent,
-            pending_writes: vec![],
-            pending_reads: vec![],
+            pending_writes: Pending::default(),
+            pending_reads: Pending::default(),
+            paradox: ParadoxReport::default(),
+            bus: vec![],
+            strategy: Box::new(Naive),
+            unit: TimeUnit::default(),
+            power: PowerModel::default(),
+            stats: Stats::default(),
+            config,
         }
     }
 
     /// Pushes, overwriting existing state if necessary
-    pub fn push_state(&mut self, x: Machine) {
+    pub fn push_state(&mut self, x: Machine) -> Result<(), EmuError> {
         dprint!("push_state t0={:?} t={:?} len={:?} ", self.timeline.t0, self.t, self.timeline.states.len());
         self.t += 1;
         // Insert at immediately next time: ok
@@ -146,13 +306,15 @@ impl Universe {
             dprintln!("(overwrite)");
             self.timeline[self.t] = x;
         } 
-        // Insert anywhere else: fail
+        // Insert anywhere else: fail (disconnected timeline, or too far into 
+        // the past/future)
         else {
-            panic!("Tried to insert state into the future: disconnected timeline. (Or, too far into the past/future)")
+            return Err(EmuError::OutOfWindow { t: self.t - 1, offset: 1 })
         }
+        Ok(())
     }
 
-    pub fn push_new_state(&mut self) {
+    pub fn push_new_state(&mut self) -> Result<(), EmuError> {
         dprintln!("push_new_state now=t={:?}", self.t);
         self.push_state(self.now().clone())
     }
@@ -165,6 +327,11 @@ impl Universe {
         &self.timeline[self.t]
     }
 
+    /// The states kept, to inspect
+    pub fn view(&self) -> TimelineView<'_> {
+        TimelineView::new(&self.timeline, self.t)
+    }
+
     pub fn now_mut(&mut self) -> &mut Machine {
         &mut self.timeline[self.t]
     }
@@ -177,9 +344,25 @@ impl Universe {
         &mut self.timeline[self.t + delta/* - 1*/]
     }*/
 
+    /// Clock cycles elapsed up to the present
+    pub fn cycles(&self) -> u64 {
+        self.now().cycles
+    }
+
     pub fn is_consistent(&self) -> bool {
         matches!(self.mode, Mode::Consistent)
     }
+
+    /// Whether the timeline can no longer change: the CPU at the present is 
+    /// stopped, the timeline consistent, and no reads or writes are pending 
+    /// in the future
+    pub fn is_settled(&self) -> bool {
+        let cpu = &self.now().cpu;
+        (cpu.halted || cpu.fault.is_some())
+        && self.is_consistent()
+        && !self.pending_reads.any_after(self.t)
+        && !self.pending_writes.any_after(self.t)
+    }
 }
 
 impl std::ops::Index<usize> for Universe {
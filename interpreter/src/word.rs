@@ -15,7 +15,8 @@ impl uWord {
     pub const MAX: Self = uWord((1 << WORD_SIZE) as u8 - 1);
     pub const ZERO: Self = uWord(0);
 
-    pub fn as_iword(self) -> iWord { iWord(self.0 as i8) }
+    /// Reinterpret as two's complement (sign-extends bit 5)
+    pub fn as_iword(self) -> iWord { iWord(((self.0 << 2) as i8) >> 2) }
     pub fn sign_bit(self) -> bool { self.0 & (1 << (WORD_SIZE-1)) != 0 }
 
     /// Convenience function
@@ -32,7 +33,8 @@ impl iWord {
     pub const MAX: Self = iWord((1 << (WORD_SIZE - 1)) as i8 - 1);
     pub const ZERO: Self = iWord(0);
 
-    pub fn as_uword(self) -> uWord { uWord(self.0 as u8) }
+    /// Reinterpret as unsigned (keeps the low 6 bits)
+    pub fn as_uword(self) -> uWord { uWord(self.0 as u8 & uWord::MAX.0) }
     pub fn sign_bit(self) -> bool { self.0 & (1 << (WORD_SIZE-1)) != 0 }
 }
 